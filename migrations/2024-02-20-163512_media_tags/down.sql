@@ -0,0 +1,3 @@
+ALTER TABLE medias DROP COLUMN album;
+ALTER TABLE medias DROP COLUMN track;
+ALTER TABLE medias DROP COLUMN release_date;
@@ -0,0 +1,3 @@
+ALTER TABLE medias ADD album TEXT;
+ALTER TABLE medias ADD track INTEGER;
+ALTER TABLE medias ADD release_date TEXT;
@@ -0,0 +1 @@
+ALTER TABLE medias ADD extractor TEXT;
@@ -0,0 +1,13 @@
+CREATE TABLE jobs(
+  id INTEGER NOT NULL PRIMARY KEY,
+  playlist_id INTEGER NOT NULL,
+  url TEXT NOT NULL,
+  position TEXT NOT NULL,
+  prefer_list BOOLEAN NOT NULL DEFAULT FALSE,
+  status TEXT NOT NULL DEFAULT 'pending',
+  error TEXT,
+  add_timestamp DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP,
+  update_timestamp DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX jobs_status ON jobs(status);
@@ -0,0 +1,12 @@
+ALTER TABLE media_lists ADD media_ids TEXT NOT NULL DEFAULT '';
+
+UPDATE media_lists SET media_ids = coalesce((
+  SELECT group_concat(media_id, ',')
+  FROM (
+    SELECT media_id FROM media_list_entries
+    WHERE media_list_id = media_lists.id
+    ORDER BY position
+  )
+), '');
+
+DROP TABLE media_list_entries;
@@ -0,0 +1,29 @@
+CREATE TABLE media_list_entries(
+  media_list_id INTEGER NOT NULL,
+  position INTEGER NOT NULL,
+  media_id INTEGER NOT NULL,
+  PRIMARY KEY (media_list_id, position)
+);
+CREATE INDEX media_list_entries_media_id ON media_list_entries(media_id);
+
+-- split the comma-separated id lists, skipping empty ones
+WITH RECURSIVE split(media_list_id, idx, media_id, rest) AS (
+  SELECT id, 0, NULL, media_ids || ',' FROM media_lists
+  UNION ALL
+  SELECT
+    media_list_id,
+    idx + 1,
+    substr(rest, 1, instr(rest, ',') - 1),
+    substr(rest, instr(rest, ',') + 1)
+  FROM split
+  WHERE rest <> ''
+)
+INSERT INTO media_list_entries(media_list_id, position, media_id)
+SELECT
+  media_list_id,
+  row_number() OVER (PARTITION BY media_list_id ORDER BY idx) - 1,
+  CAST(media_id AS INTEGER)
+FROM split
+WHERE media_id IS NOT NULL AND trim(media_id) <> '';
+
+ALTER TABLE media_lists DROP COLUMN media_ids;
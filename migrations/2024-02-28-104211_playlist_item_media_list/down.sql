@@ -0,0 +1 @@
+ALTER TABLE playlist_items DROP COLUMN media_list_id;
@@ -0,0 +1 @@
+ALTER TABLE playlist_items ADD media_list_id INTEGER;
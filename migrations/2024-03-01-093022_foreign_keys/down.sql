@@ -0,0 +1,39 @@
+CREATE TABLE playlist_items_old(
+  id INTEGER NOT NULL PRIMARY KEY,
+  playlist_id INTEGER NOT NULL,
+  media_id INTEGER NOT NULL,
+  prev INTEGER,
+  next INTEGER,
+  add_timestamp DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP,
+  media_list_id INTEGER
+);
+INSERT INTO playlist_items_old SELECT * FROM playlist_items;
+DROP TABLE playlist_items;
+ALTER TABLE playlist_items_old RENAME TO playlist_items;
+
+CREATE TABLE jobs_old(
+  id INTEGER NOT NULL PRIMARY KEY,
+  playlist_id INTEGER NOT NULL,
+  url TEXT NOT NULL,
+  position TEXT NOT NULL,
+  prefer_list BOOLEAN NOT NULL DEFAULT FALSE,
+  status TEXT NOT NULL DEFAULT 'pending',
+  error TEXT,
+  add_timestamp DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP,
+  update_timestamp DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+INSERT INTO jobs_old SELECT * FROM jobs;
+DROP TABLE jobs;
+ALTER TABLE jobs_old RENAME TO jobs;
+CREATE INDEX jobs_status ON jobs(status);
+
+CREATE TABLE media_list_entries_old(
+  media_list_id INTEGER NOT NULL,
+  position INTEGER NOT NULL,
+  media_id INTEGER NOT NULL,
+  PRIMARY KEY (media_list_id, position)
+);
+INSERT INTO media_list_entries_old SELECT * FROM media_list_entries;
+DROP TABLE media_list_entries;
+ALTER TABLE media_list_entries_old RENAME TO media_list_entries;
+CREATE INDEX media_list_entries_media_id ON media_list_entries(media_id);
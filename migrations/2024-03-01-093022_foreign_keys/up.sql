@@ -0,0 +1,65 @@
+-- SQLite can not add constraints to existing tables, so they are rebuilt.
+-- Rows that would violate the new constraints are dropped on the way.
+
+CREATE TABLE playlist_items_new(
+  id INTEGER NOT NULL PRIMARY KEY,
+  playlist_id INTEGER NOT NULL REFERENCES playlists(id) ON DELETE CASCADE,
+  media_id INTEGER NOT NULL REFERENCES medias(id),
+  prev INTEGER,
+  next INTEGER,
+  add_timestamp DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP,
+  media_list_id INTEGER REFERENCES media_lists(id) ON DELETE SET NULL
+);
+
+INSERT INTO playlist_items_new
+SELECT
+  id,
+  playlist_id,
+  media_id,
+  prev,
+  next,
+  add_timestamp,
+  (SELECT id FROM media_lists WHERE id = playlist_items.media_list_id)
+FROM playlist_items
+WHERE playlist_id IN (SELECT id FROM playlists)
+  AND media_id IN (SELECT id FROM medias);
+
+DROP TABLE playlist_items;
+ALTER TABLE playlist_items_new RENAME TO playlist_items;
+CREATE INDEX playlist_items_playlist_id ON playlist_items(playlist_id);
+CREATE INDEX playlist_items_media_id ON playlist_items(media_id);
+
+CREATE TABLE jobs_new(
+  id INTEGER NOT NULL PRIMARY KEY,
+  playlist_id INTEGER NOT NULL REFERENCES playlists(id) ON DELETE CASCADE,
+  url TEXT NOT NULL,
+  position TEXT NOT NULL,
+  prefer_list BOOLEAN NOT NULL DEFAULT FALSE,
+  status TEXT NOT NULL DEFAULT 'pending',
+  error TEXT,
+  add_timestamp DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP,
+  update_timestamp DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+INSERT INTO jobs_new
+SELECT * FROM jobs WHERE playlist_id IN (SELECT id FROM playlists);
+
+DROP TABLE jobs;
+ALTER TABLE jobs_new RENAME TO jobs;
+CREATE INDEX jobs_status ON jobs(status);
+
+CREATE TABLE media_list_entries_new(
+  media_list_id INTEGER NOT NULL REFERENCES media_lists(id) ON DELETE CASCADE,
+  position INTEGER NOT NULL,
+  media_id INTEGER NOT NULL REFERENCES medias(id) ON DELETE CASCADE,
+  PRIMARY KEY (media_list_id, position)
+);
+
+INSERT INTO media_list_entries_new
+SELECT * FROM media_list_entries
+WHERE media_list_id IN (SELECT id FROM media_lists)
+  AND media_id IN (SELECT id FROM medias);
+
+DROP TABLE media_list_entries;
+ALTER TABLE media_list_entries_new RENAME TO media_list_entries;
+CREATE INDEX media_list_entries_media_id ON media_list_entries(media_id);
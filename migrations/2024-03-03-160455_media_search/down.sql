@@ -0,0 +1,4 @@
+DROP TRIGGER medias_fts_update;
+DROP TRIGGER medias_fts_delete;
+DROP TRIGGER medias_fts_insert;
+DROP TABLE medias_fts;
@@ -0,0 +1,27 @@
+CREATE VIRTUAL TABLE medias_fts USING fts5(
+  title,
+  artist,
+  alt_title,
+  alt_artist,
+  content='medias',
+  content_rowid='id'
+);
+
+INSERT INTO medias_fts(medias_fts) VALUES('rebuild');
+
+CREATE TRIGGER medias_fts_insert AFTER INSERT ON medias BEGIN
+  INSERT INTO medias_fts(rowid, title, artist, alt_title, alt_artist)
+  VALUES (new.id, new.title, new.artist, new.alt_title, new.alt_artist);
+END;
+
+CREATE TRIGGER medias_fts_delete AFTER DELETE ON medias BEGIN
+  INSERT INTO medias_fts(medias_fts, rowid, title, artist, alt_title, alt_artist)
+  VALUES ('delete', old.id, old.title, old.artist, old.alt_title, old.alt_artist);
+END;
+
+CREATE TRIGGER medias_fts_update AFTER UPDATE OF title, artist, alt_title, alt_artist ON medias BEGIN
+  INSERT INTO medias_fts(medias_fts, rowid, title, artist, alt_title, alt_artist)
+  VALUES ('delete', old.id, old.title, old.artist, old.alt_title, old.alt_artist);
+  INSERT INTO medias_fts(rowid, title, artist, alt_title, alt_artist)
+  VALUES (new.id, new.title, new.artist, new.alt_title, new.alt_artist);
+END;
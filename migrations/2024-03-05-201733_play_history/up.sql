@@ -0,0 +1,13 @@
+CREATE TABLE play_history(
+  id INTEGER NOT NULL PRIMARY KEY,
+  media_id INTEGER NOT NULL REFERENCES medias(id) ON DELETE CASCADE,
+  -- history outlives playlists and their items
+  playlist_id INTEGER REFERENCES playlists(id) ON DELETE SET NULL,
+  playlist_item_id INTEGER,
+  started_at DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP,
+  ended_at DATETIME,
+  completed BOOLEAN NOT NULL DEFAULT FALSE
+);
+
+CREATE INDEX play_history_started_at ON play_history(started_at);
+CREATE INDEX play_history_playlist_id ON play_history(playlist_id);
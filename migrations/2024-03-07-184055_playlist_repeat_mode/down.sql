@@ -0,0 +1,2 @@
+ALTER TABLE playlists DROP COLUMN stop_after_current;
+ALTER TABLE playlists DROP COLUMN repeat_mode;
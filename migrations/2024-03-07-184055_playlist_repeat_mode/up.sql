@@ -0,0 +1,2 @@
+ALTER TABLE playlists ADD repeat_mode TEXT NOT NULL DEFAULT 'all';
+ALTER TABLE playlists ADD stop_after_current BOOLEAN NOT NULL DEFAULT FALSE;
@@ -0,0 +1,2 @@
+DROP TABLE shuffle_order;
+ALTER TABLE playlists DROP COLUMN shuffle;
@@ -0,0 +1,13 @@
+ALTER TABLE playlists ADD shuffle BOOLEAN NOT NULL DEFAULT FALSE;
+
+-- Play order of a shuffled playlist. Items up to the current one have been
+-- played in this cycle, the others are yet to be played.
+CREATE TABLE shuffle_order(
+  playlist_id INTEGER NOT NULL REFERENCES playlists(id) ON DELETE CASCADE,
+  position INTEGER NOT NULL,
+  playlist_item_id INTEGER NOT NULL REFERENCES playlist_items(id) ON DELETE CASCADE,
+  PRIMARY KEY (playlist_id, playlist_item_id)
+);
+
+CREATE INDEX shuffle_order_position ON shuffle_order(playlist_id, position);
+CREATE INDEX shuffle_order_playlist_item_id ON shuffle_order(playlist_item_id);
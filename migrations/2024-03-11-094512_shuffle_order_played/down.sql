@@ -0,0 +1 @@
+ALTER TABLE shuffle_order DROP COLUMN played;
@@ -0,0 +1,4 @@
+-- Items played in the current cycle come first in the play order. The
+-- current item is not necessarily the last of them, as the user can jump
+-- to any item.
+ALTER TABLE shuffle_order ADD played BOOLEAN NOT NULL DEFAULT FALSE;
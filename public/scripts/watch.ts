@@ -22,6 +22,12 @@ let current: any;
 
 const pid = document.querySelector("main")!.dataset.pid!;
 
+// media types played through /servermedia in the HTML5 player
+const serverMediaTypes = ["local", "http", "ytdl"];
+
+const usesServerPlayer = (media: any) =>
+  serverMediaTypes.includes(media?.media_type);
+
 function getYoutubeId(url: string): string {
   const prefix = "https://youtu.be/";
   return url.substring(prefix.length);
@@ -59,7 +65,7 @@ const fetchPlayer = async () => {
     return;
   }
 
-  if (current?.media_type === "local") {
+  if (usesServerPlayer(current)) {
     document.getElementById("server-player-wrapper")?.classList.add("active");
     serverVideoPlayer.currentTime = 0;
     serverVideoPlayer.setAttribute("src", `/servermedia/${current.id}`)
@@ -81,7 +87,7 @@ const playerPlay = async () => {
   if (current?.media_type === "yt") {
     const player = await getCachedYoutubePlayer("yt-player");
     player.playVideo();
-  } else if (current?.media_type === "local") {
+  } else if (usesServerPlayer(current)) {
     serverVideoPlayer.play();
   }
 };
@@ -90,7 +96,7 @@ const playerPause = async () => {
   if (current?.media_type === "yt") {
     const player = await getCachedYoutubePlayer("yt-player");
     player.pauseVideo();
-  } else if (current?.media_type === "local") {
+  } else if (usesServerPlayer(current)) {
     serverVideoPlayer.pause();
   }
 };
@@ -99,14 +105,33 @@ const playerPlaying = async () => {
   if (current?.media_type === "yt") {
     const player = await getCachedYoutubePlayer("yt-player");
     return player.getPlayerState() !== YT.PlayerState.PAUSED;
-  } else if (current?.media_type === "local") {
+  } else if (usesServerPlayer(current)) {
     return !serverVideoPlayer.paused;
   }
 
   return false;
 };
 
+// `progress:<id>:<message>` messages update a single line of the
+// diagnostics tab per operation
+const showProgress = (msg: string) => {
+  const [id, ...message] = msg.split(":");
+  const diagnostics = document.getElementById("diagnostics")!;
+  let line = document.getElementById(`progress-${id}`);
+  if (line === null) {
+    line = document.createElement("p");
+    line.id = `progress-${id}`;
+    diagnostics.prepend(line);
+  }
+  line.textContent = message.join(":");
+};
+
 socket = new ReconnectableSocket(async (msg) => {
+  if (msg.startsWith("progress:")) {
+    showProgress(msg.substring("progress:".length));
+    return;
+  }
+
   document.body.dispatchEvent(new Event(msg));
   if (msg === "media-changed") {
     fetchPlayer();
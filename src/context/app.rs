@@ -1,22 +1,35 @@
 use super::{
+    export::export_router,
+    history::history_router,
+    jobs::{jobs_router, JobQueue},
     playlist::playlist_router,
     ssr::ssr_router,
     static_files::static_file_router,
+    stats::stats_router,
     ws::{ws_router, SocketId, SocketSink},
     ResponseResult,
 };
 use crate::{
     db::{
         establish_connection,
+        history::{end_play, start_play},
+        immediate_transaction,
         media::{
-            increase_media_view_count, insert_media, insert_media_list, query_media_list_with_url,
-            query_media_with_id, query_media_with_url, Media, MediaOrMediaList,
+            increase_media_view_count, insert_media_list, insert_or_get_media,
+            query_media_list_with_url, query_media_with_id, query_media_with_url, Media, MediaList,
+            MediaOrMediaList, NewMediaList,
+        },
+        playlist::{
+            append_to_playlist, query_playlist_from_id, update_playlist_current_item,
+            update_playlist_stop_after_current, AddPosition, Playlist, PlaylistId, RepeatMode,
         },
-        playlist::{query_playlist_from_id, update_playlist_current_item, Playlist, PlaylistId},
         playlist_item::{query_playlist_item, PlaylistItem, PlaylistItemId},
+        shuffle::{next_shuffled_item, prev_shuffled_item},
         ResourceQueryError, ResourceQueryResult, SqliteConnectionPool,
     },
-    resolvers::{normalize_media_url, resolve_media, resolve_media_list, MediaResolveError},
+    resolvers::{
+        ytdl::DirectUrlCache, MediaListEntry, MediaResolveError, ResolveFailures, ResolverRegistry,
+    },
 };
 use anyhow::{Context, Result};
 use axum::{extract::ws::Message, Router};
@@ -25,16 +38,21 @@ use diesel::{r2d2::ConnectionManager, SqliteConnection};
 #[cfg(feature = "discord-rich-presence")]
 use discord_presence::models::Activity;
 
-use futures::SinkExt;
+use futures::{SinkExt, StreamExt};
+use lazy_static::lazy_static;
 use r2d2::PooledConnection;
 use std::{
     collections::{hash_map::Entry, HashMap},
-    sync::{Arc, Weak},
+    sync::{
+        atomic::{AtomicU32, Ordering},
+        Arc, Weak,
+    },
 };
 use thiserror::Error;
 use tokio::sync::Mutex;
 use tower::ServiceBuilder;
 use tower_http::{compression::CompressionLayer, trace::TraceLayer};
+use url::Url;
 
 #[cfg(feature = "notifications")]
 use notify_rust::Notification;
@@ -133,10 +151,35 @@ impl MediaControlState {
     }
 }
 
+#[cfg(test)]
+impl MediaControlState {
+    /// Controls that are never attached, so that tests need neither a
+    /// session bus nor a Discord client.
+    fn detached() -> Self {
+        Self {
+            #[cfg(feature = "media-controls")]
+            os_media_controls: Mutex::new(
+                MediaControls::new(PlatformConfig {
+                    display_name: "plst3",
+                    dbus_name: "plst3",
+                    hwnd: None,
+                })
+                .unwrap(),
+            ),
+            #[cfg(feature = "discord-rich-presence")]
+            discord_rpc: Mutex::new(discord_presence::Client::new(0)),
+            status: Mutex::new(MediaStatus::Stopped),
+        }
+    }
+}
+
 pub struct AppState {
     db_pool: SqliteConnectionPool,
     sockets: Mutex<HashMap<PlaylistId, SocketSinkContainer>>,
     media_state: MediaControlState,
+    resolvers: ResolverRegistry,
+    jobs: JobQueue,
+    direct_urls: DirectUrlCache,
 }
 
 pub type AppRouter = Router<Arc<AppState>>;
@@ -183,6 +226,47 @@ impl SocketSinkContainer {
     }
 }
 
+/// Progress of a long running operation, shown in the diagnostics tab of the
+/// watch pages of a playlist.
+#[derive(Clone, Copy)]
+pub struct ProgressReporter {
+    playlist_id: PlaylistId,
+    id: u32,
+}
+
+impl ProgressReporter {
+    pub fn new(playlist_id: PlaylistId) -> Self {
+        static ID: AtomicU32 = AtomicU32::new(0);
+        Self {
+            playlist_id,
+            id: ID.fetch_add(1, Ordering::Relaxed),
+        }
+    }
+}
+
+lazy_static! {
+    static ref RESOLVE_CONCURRENCY: usize = std::env::var("RESOLVE_CONCURRENCY")
+        .ok()
+        .and_then(|env| env.parse::<usize>().ok())
+        .filter(|concurrency| *concurrency > 0)
+        .unwrap_or(4);
+}
+
+/// Sum of the durations of `medias`, in seconds.
+pub fn total_duration_of(medias: &[Media]) -> i32 {
+    medias
+        .iter()
+        .map(|media| {
+            media
+                .duration
+                .map(|d| d.whole_seconds())
+                .unwrap_or_default()
+        })
+        .sum::<i64>()
+        .try_into()
+        .unwrap_or(i32::MAX)
+}
+
 #[derive(Error, Debug)]
 pub enum FetchMediaError {
     #[error("Database error: {0}")]
@@ -200,14 +284,31 @@ impl AppState {
                 .context("unable to establish connection to database")?,
             sockets: Mutex::new(HashMap::new()),
             media_state: MediaControlState::new()?,
+            resolvers: ResolverRegistry::default(),
+            jobs: JobQueue::default(),
+            direct_urls: DirectUrlCache::default(),
         });
 
         app.media_state.attach_to_app(Arc::downgrade(&app)).await;
         app.update_media_metadata(true).await.ok();
+        JobQueue::start(&app).context("unable to start job workers")?;
 
         Ok(app)
     }
 
+    /// An app without job workers nor attached media controls.
+    #[cfg(test)]
+    pub fn detached(db_pool: SqliteConnectionPool) -> Arc<Self> {
+        Arc::new(Self {
+            db_pool,
+            sockets: Mutex::new(HashMap::new()),
+            media_state: MediaControlState::detached(),
+            resolvers: ResolverRegistry::default(),
+            jobs: JobQueue::default(),
+            direct_urls: DirectUrlCache::default(),
+        })
+    }
+
     #[cfg(feature = "media-controls")]
     async fn handle_event(self: &Arc<Self>, event: MediaControlEvent) -> Result<()> {
         let playlist_id = match *self.media_state.status.lock().await {
@@ -235,6 +336,9 @@ impl AppState {
                     self.prev(&mut db_conn, playlist_id).await?;
                 }
                 MediaControlEvent::OpenUri(_) => todo!(),
+                // souvlaki 0.7 implements neither the LoopStatus nor the
+                // Shuffle property, so repeat modes and shuffle play can only
+                // be changed from the web controller
                 _ => {}
             }
         }
@@ -245,6 +349,10 @@ impl AppState {
     pub fn create_router(self: Arc<Self>) -> Router {
         Router::new()
             .merge(playlist_router())
+            .merge(export_router())
+            .merge(jobs_router())
+            .merge(history_router())
+            .merge(stats_router())
             .merge(ssr_router())
             .merge(static_file_router())
             .merge(ws_router())
@@ -274,15 +382,29 @@ impl AppState {
         self.db_pool.get()
     }
 
+    pub fn resolvers(&self) -> &ResolverRegistry {
+        &self.resolvers
+    }
+
+    pub fn jobs(&self) -> &JobQueue {
+        &self.jobs
+    }
+
+    pub fn direct_urls(&self) -> &DirectUrlCache {
+        &self.direct_urls
+    }
+
     pub async fn fetch_media(
         &self,
         db_conn: &mut SqliteConnection,
         media_url: &str,
     ) -> Result<Media, FetchMediaError> {
-        let media_url = normalize_media_url(media_url)
+        let media_url = self
+            .resolvers
+            .normalize_media_url(media_url)
             .await
             .map_err(FetchMediaError::InvalidUrl)?;
-        match query_media_with_url(db_conn, &media_url) {
+        match query_media_with_url(db_conn, &self.resolvers.cache_url(&media_url, false)) {
             Ok(media) => return Ok(media),
             Err(ResourceQueryError::DatabaseError(e)) => {
                 return Err(FetchMediaError::DatabaseError(e))
@@ -290,95 +412,249 @@ impl AppState {
             _ => {}
         }
 
-        let media = resolve_media(&media_url, None)
+        let media = self
+            .resolvers
+            .resolve_media(&media_url, None)
             .await
             .map_err(FetchMediaError::ResolveError)?;
-        insert_media(db_conn, media).map_err(FetchMediaError::DatabaseError)
+        insert_or_get_media(db_conn, media).map_err(FetchMediaError::DatabaseError)
     }
 
+    /// Fetch the media or media list referenced by `media_url`.
+    ///
+    /// Some urls (e.g. YouTube videos in a playlist) reference both, in which
+    /// case `prefer_list` decides which one is returned.
     pub async fn fetch_medias(
         &self,
         db_conn: &mut SqliteConnection,
         media_url: &str,
+        prefer_list: bool,
+        progress: Option<ProgressReporter>,
     ) -> Result<MediaOrMediaList, FetchMediaError> {
-        let media_url = normalize_media_url(media_url)
+        let media_url = self
+            .resolvers
+            .normalize_media_url(media_url)
             .await
             .map_err(FetchMediaError::InvalidUrl)?;
         tracing::info!("fetching media with url: {media_url}");
-        match query_media_with_url(db_conn, &media_url) {
-            Ok(media) => return Ok(media.into()),
-            Err(ResourceQueryError::DatabaseError(e)) => {
-                return Err(FetchMediaError::DatabaseError(e))
+        for list in [prefer_list, !prefer_list] {
+            let cache_url = self.resolvers.cache_url(&media_url, list);
+            let cached = if list {
+                query_media_list_with_url(db_conn, &cache_url).map(Into::into)
+            } else {
+                query_media_with_url(db_conn, &cache_url).map(Into::into)
+            };
+            match cached {
+                Ok(medias) => return Ok(medias),
+                Err(ResourceQueryError::DatabaseError(e)) => {
+                    return Err(FetchMediaError::DatabaseError(e))
+                }
+                _ => {}
             }
-            _ => {}
         }
-        match query_media_list_with_url(db_conn, &media_url) {
-            Ok(media_list) => return Ok(media_list.into()),
-            Err(ResourceQueryError::DatabaseError(e)) => {
-                return Err(FetchMediaError::DatabaseError(e))
+
+        let mut failures = ResolveFailures::default();
+        for list in [prefer_list, !prefer_list] {
+            if list {
+                match self.resolvers.resolve_media_list(&media_url).await {
+                    Ok((media_list, entries)) => {
+                        return self
+                            .insert_resolved_media_list(db_conn, media_list, entries, progress)
+                            .await
+                            .map(Into::into)
+                    }
+                    Err(e) => failures.push(e).map_err(FetchMediaError::ResolveError)?,
+                }
+            } else {
+                match self.resolvers.resolve_media(&media_url, None).await {
+                    Ok(media) => {
+                        return insert_or_get_media(db_conn, media)
+                            .map(Into::into)
+                            .map_err(FetchMediaError::DatabaseError)
+                    }
+                    Err(e) => failures.push(e).map_err(FetchMediaError::ResolveError)?,
+                }
             }
-            _ => {}
         }
 
-        let mut unsupported = false;
-        let mut invalid = false;
-        let mut not_found = false;
-        match resolve_media(&media_url, None).await {
-            Ok(media) => {
-                return insert_media(db_conn, media)
-                    .map(Into::into)
-                    .map_err(FetchMediaError::DatabaseError)
+        Err(FetchMediaError::ResolveError(failures.into_error()))
+    }
+
+    async fn insert_resolved_media_list(
+        &self,
+        db_conn: &mut SqliteConnection,
+        mut media_list: NewMediaList<'_>,
+        entries: Vec<MediaListEntry>,
+        progress: Option<ProgressReporter>,
+    ) -> Result<MediaList, FetchMediaError> {
+        // resolvers may store the list under a different url than requested
+        if let Ok(list_url) = Url::parse(&media_list.url) {
+            match query_media_list_with_url(db_conn, &list_url) {
+                Ok(media_list) => return Ok(media_list),
+                Err(ResourceQueryError::DatabaseError(e)) => {
+                    return Err(FetchMediaError::DatabaseError(e))
+                }
+                _ => {}
             }
-            Err(e) if matches!(e, MediaResolveError::FailedProcessing(_)) => {
-                return Err(FetchMediaError::ResolveError(e))
+        }
+
+        let title = media_list.title.clone();
+        let medias = self
+            .resolve_media_list_entries(db_conn, &title, entries, progress)
+            .await?;
+        let media_ids = medias.iter().map(|media| media.id).collect::<Vec<_>>();
+        media_list.total_duration = total_duration_of(&medias);
+        insert_media_list(db_conn, media_list, &media_ids).map_err(FetchMediaError::DatabaseError)
+    }
+
+    /// Turn resolved media list entries into cached medias, resolving the
+    /// entries that came without metadata.
+    ///
+    /// Entries that fail to resolve (e.g. private or removed videos) are
+    /// skipped and reported, unless none of the entries could be resolved.
+    pub async fn resolve_media_list_entries(
+        &self,
+        db_conn: &mut SqliteConnection,
+        title: &str,
+        entries: Vec<MediaListEntry>,
+        progress: Option<ProgressReporter>,
+    ) -> Result<Vec<Media>, FetchMediaError> {
+        let total = entries.len();
+        let mut medias = Vec::with_capacity(total);
+        let mut pending = Vec::new();
+        let mut failure = None;
+        let mut skipped = 0;
+        for (index, entry) in entries.into_iter().enumerate() {
+            let media_url = match self.resolvers.normalize_media_url(&entry.url).await {
+                Ok(media_url) => media_url,
+                Err(e) => {
+                    tracing::warn!("skipping media list entry {}: {e}", entry.url);
+                    medias.push(None);
+                    failure.get_or_insert(FetchMediaError::InvalidUrl(e));
+                    skipped += 1;
+                    continue;
+                }
+            };
+            let cache_url = self.resolvers.cache_url(&media_url, false);
+            match query_media_with_url(db_conn, &cache_url) {
+                Ok(media) => {
+                    medias.push(Some(media));
+                    continue;
+                }
+                Err(ResourceQueryError::DatabaseError(e)) => {
+                    return Err(FetchMediaError::DatabaseError(e))
+                }
+                _ => {}
             }
-            Err(MediaResolveError::UnsupportedUrl) => unsupported = true,
-            Err(MediaResolveError::InvalidMedia) => invalid = true,
-            Err(MediaResolveError::MediaNotFound) => not_found = true,
-            _ => {}
-        };
 
-        match resolve_media_list(&media_url).await {
-            Ok((mut media_list, media_urls)) => {
-                let mut media_ids = Vec::with_capacity(media_urls.len());
-                for media_url in media_urls {
-                    let id = self.fetch_media(db_conn, &media_url).await?.id;
-                    media_ids.push(id);
+            match entry.metadata {
+                Some(mut metadata) => {
+                    metadata.url = cache_url.to_string().into();
+                    medias.push(Some(insert_or_get_media(db_conn, metadata)?));
+                }
+                None => {
+                    medias.push(None);
+                    pending.push((index, media_url));
                 }
-                media_list.media_ids = media_ids
-                    .iter()
-                    .map(|id| id.to_string())
-                    .collect::<Vec<_>>()
-                    .join(",")
-                    .into();
-                return insert_media_list(db_conn, media_list)
-                    .map(Into::into)
-                    .map_err(FetchMediaError::DatabaseError);
             }
-            Err(e) if matches!(e, MediaResolveError::FailedProcessing(_)) => {
-                return Err(FetchMediaError::ResolveError(e))
+        }
+
+        // resolving is slow (one yt-dlp or ffprobe process per media), so it
+        // is done concurrently, while database work stays on this connection
+        let mut resolved = total - pending.len() - skipped;
+        let mut results = futures::stream::iter(pending)
+            .map(|(index, media_url)| async move {
+                let media = self.resolvers.resolve_media(&media_url, None).await;
+                (index, media_url, media)
+            })
+            .buffer_unordered(*RESOLVE_CONCURRENCY);
+        while let Some((index, media_url, media)) = results.next().await {
+            match media {
+                Ok(media) => {
+                    medias[index] = Some(insert_or_get_media(db_conn, media)?);
+                    resolved += 1;
+                }
+                Err(e) => {
+                    tracing::warn!("skipping media list entry {media_url}: {e}");
+                    failure.get_or_insert(FetchMediaError::ResolveError(e));
+                    skipped += 1;
+                }
             }
-            Err(MediaResolveError::UnsupportedUrl) => unsupported = true,
-            Err(MediaResolveError::InvalidMedia) => invalid = true,
-            Err(MediaResolveError::MediaNotFound) => not_found = true,
-            _ => {}
-        };
+            self.report_progress(
+                progress,
+                &format!("{title}: resolved {resolved}/{total} medias"),
+            )
+            .await;
+        }
+        drop(results);
 
-        if not_found {
-            Err(FetchMediaError::ResolveError(
-                MediaResolveError::MediaNotFound,
-            ))
-        } else if invalid {
-            Err(FetchMediaError::ResolveError(
-                MediaResolveError::InvalidMedia,
-            ))
-        } else if unsupported {
-            Err(FetchMediaError::ResolveError(
-                MediaResolveError::UnsupportedUrl,
-            ))
-        } else {
-            unreachable!("either one of the above must be true")
+        let medias = medias.into_iter().flatten().collect::<Vec<_>>();
+        if let Some(failure) = failure.filter(|_| medias.is_empty()) {
+            return Err(failure);
         }
+        let message = if skipped > 0 {
+            format!(
+                "{title}: added {} medias, skipped {skipped} that could not be resolved",
+                medias.len()
+            )
+        } else {
+            format!("{title}: added {} medias", medias.len())
+        };
+        self.report_progress(progress, &message).await;
+        Ok(medias)
+    }
+
+    /// Add `medias` to a playlist, returning the first added item, if any.
+    pub async fn add_to_playlist(
+        self: &Arc<Self>,
+        db_conn: &mut SqliteConnection,
+        playlist_id: PlaylistId,
+        position: AddPosition,
+        medias: &MediaOrMediaList,
+    ) -> ResourceQueryResult<Option<PlaylistItemId>> {
+        self.add_to_playlist_if(db_conn, playlist_id, position, medias, |_| Ok(true))
+            .await
+    }
+
+    /// Same as [`Self::add_to_playlist`], but nothing is added unless `check`
+    /// returns `true`. `check` runs in the same transaction as the append.
+    pub async fn add_to_playlist_if(
+        self: &Arc<Self>,
+        db_conn: &mut SqliteConnection,
+        playlist_id: PlaylistId,
+        position: AddPosition,
+        medias: &MediaOrMediaList,
+        check: impl FnOnce(&mut SqliteConnection) -> ResourceQueryResult<bool>,
+    ) -> ResourceQueryResult<Option<PlaylistItemId>> {
+        let added = immediate_transaction(db_conn, |db_conn| {
+            if !check(db_conn)? {
+                return Ok(None);
+            }
+            let playlist = query_playlist_from_id(db_conn, playlist_id)?;
+            let pivot = match position {
+                AddPosition::AddToStart => None,
+                AddPosition::QueueNext => playlist.current_item,
+                AddPosition::AddToEnd => playlist.last_playlist_item,
+            };
+            let total_duration = medias.total_duration();
+            let media_ids = medias.media_ids(db_conn)?;
+            let item_ids = append_to_playlist(
+                db_conn,
+                playlist.id,
+                pivot,
+                &media_ids,
+                medias.media_list_id(),
+                total_duration,
+            )?;
+            Ok::<_, ResourceQueryError>(item_ids.first().map(|&item_id| (playlist, item_id)))
+        })?;
+        let Some((playlist, first_item_id)) = added else {
+            return Ok(None);
+        };
+        #[cfg(feature = "notifications")]
+        self.notify_playlist_add(&playlist, medias, first_item_id);
+        self.refresh_playlist(playlist.id).await;
+        Ok(Some(first_item_id))
     }
 
     pub async fn set_current_playlist(
@@ -429,6 +705,13 @@ impl AppState {
         }
     }
 
+    pub async fn report_progress(&self, reporter: Option<ProgressReporter>, message: &str) {
+        if let Some(ProgressReporter { playlist_id, id }) = reporter {
+            self.send_message(playlist_id, &format!("progress:{id}:{message}"))
+                .await;
+        }
+    }
+
     pub async fn refresh_playlist(&self, playlist_id: PlaylistId) {
         self.send_message(playlist_id, "refresh-playlist").await;
     }
@@ -480,7 +763,7 @@ impl AppState {
                         .set_metadata(MediaMetadata {
                             title: media.as_ref().map(|m| m.display_title()),
                             artist: media.as_ref().map(|m| m.display_artist()),
-                            album: None,
+                            album: media.as_ref().and_then(|m| m.album.as_deref()),
                             cover_url: None,
                             duration: media.as_ref().and_then(|m| m.duration).map(|d| {
                                 std::time::Duration::new(
@@ -499,6 +782,10 @@ impl AppState {
             if discord_presence::Client::is_ready() && media_changed {
                 let app = self.clone();
                 let media = media.clone();
+                let thumbnail_url = media.as_ref().and_then(|media| {
+                    self.resolvers
+                        .get_media_thumbnail_url(&media.media_type, &media.url)
+                });
                 tokio::task::spawn_blocking(move || {
                     tracing::debug!("updating discord rich presence to media {media:?}");
                     app.media_state.discord_rpc.blocking_lock().set_activity(move |_| {
@@ -513,10 +800,8 @@ impl AppState {
                             a = a.timestamps(|ts| ts.start(time::OffsetDateTime::now_utc().unix_timestamp() as _));
                         }
                         a.assets(|mut ass| {
-                            if let Some(media) = media.as_ref() {
-                                if let Some(thumbnail_url) = crate::resolvers::get_media_thumbnail_url(&media.media_type, &media.url) {
-                                    ass = ass.large_image(thumbnail_url).large_text(media.display_title());
-                                }
+                            if let Some((media, thumbnail_url)) = media.as_ref().zip(thumbnail_url) {
+                                ass = ass.large_image(thumbnail_url).large_text(media.album.as_deref().unwrap_or(media.display_title()));
                             }
                             ass.small_text("plst3")
                                .small_image("https://raw.githubusercontent.com/btmxh/plst3/master/public/assets/plst.png")
@@ -536,7 +821,7 @@ impl AppState {
     pub async fn media_changed(
         self: &Arc<Self>,
         playlist_id: PlaylistId,
-        media: Option<&Media>,
+        current: Option<(&PlaylistItem, &Media)>,
     ) -> Result<()> {
         if let Some(sockets) = self.sockets.lock().await.get_mut(&playlist_id) {
             sockets.reset();
@@ -550,11 +835,24 @@ impl AppState {
                 })
                 .ok();
         }
-        if let Some(media) = media {
-            let mut db_conn = self.acquire_db_connection()?;
+        let mut db_conn = self.acquire_db_connection()?;
+        if let Some((item, media)) = current {
+            if media.media_type == "ytdl" {
+                // resolve the direct url before the players request it
+                let app = self.clone();
+                let (media_id, media_url) = (media.id, media.url.clone());
+                tokio::spawn(async move {
+                    if let Err(e) = app.direct_urls.get_or_resolve(media_id, &media_url).await {
+                        tracing::warn!("unable to resolve direct url of {media_url}: {e}");
+                    }
+                });
+            }
             #[cfg(feature = "notifications")]
             self.notify_playlist_item_change(&mut db_conn, playlist_id, media)?;
             increase_media_view_count(&mut db_conn, media.id)?;
+            start_play(&mut db_conn, playlist_id, item.id, media.id)?;
+        } else {
+            end_play(&mut db_conn, playlist_id, false)?;
         }
         Ok(())
     }
@@ -652,44 +950,83 @@ impl AppState {
         update_playlist_current_item(db_conn, playlist_id, Some(item_id))?;
         let item = query_playlist_item(db_conn, item_id)?;
         let media = query_media_with_id(db_conn, item.media_id)?;
-        self.media_changed(playlist_id, Some(&media)).await?;
+        self.media_changed(playlist_id, Some((&item, &media)))
+            .await?;
         Ok(())
     }
 
+    /// Skip to the next item, wrapping around to the first one unless the
+    /// playlist does not repeat. Shuffled playlists follow their play order
+    /// instead. Returns whether the current item changed.
     pub async fn next(
         self: &Arc<Self>,
         db_conn: &mut SqliteConnection,
         playlist_id: PlaylistId,
+    ) -> ResponseResult<bool> {
+        let playlist = query_playlist_from_id(db_conn, playlist_id)?;
+        let wrap = playlist.repeat_mode != RepeatMode::None;
+        let next = if playlist.shuffle {
+            next_shuffled_item(db_conn, playlist_id, wrap, &mut rand::thread_rng())?
+        } else if let Some(current_item) = Self::get_current_item(db_conn, playlist_id)? {
+            current_item
+                .next
+                .or(playlist.first_playlist_item.filter(|_| wrap))
+        } else {
+            None
+        };
+        if let Some(next) = next {
+            self.set_playlist_item_as_current(db_conn, Some(playlist_id), next)
+                .await?;
+        }
+        Ok(next.is_some())
+    }
+
+    /// Go back to the previous item, wrapping around to the last one unless
+    /// the playlist does not repeat. Shuffled playlists go back to the
+    /// previously played item instead.
+    pub async fn prev(
+        self: &Arc<Self>,
+        db_conn: &mut SqliteConnection,
+        playlist_id: PlaylistId,
     ) -> ResponseResult<()> {
-        if let Some(current_item) = Self::get_current_item(db_conn, playlist_id)? {
-            if let Some(next) = current_item.next {
-                self.set_playlist_item_as_current(db_conn, Some(playlist_id), next)
-                    .await?;
-            } else if let Some(item) =
-                query_playlist_from_id(db_conn, playlist_id)?.first_playlist_item
-            {
-                self.set_playlist_item_as_current(db_conn, Some(playlist_id), item)
-                    .await?;
-            }
+        let playlist = query_playlist_from_id(db_conn, playlist_id)?;
+        let wrap = playlist.repeat_mode != RepeatMode::None;
+        let prev = if playlist.shuffle {
+            prev_shuffled_item(db_conn, playlist_id)?
+        } else if let Some(current_item) = Self::get_current_item(db_conn, playlist_id)? {
+            current_item
+                .prev
+                .or(playlist.last_playlist_item.filter(|_| wrap))
+        } else {
+            None
+        };
+        if let Some(prev) = prev {
+            self.set_playlist_item_as_current(db_conn, Some(playlist_id), prev)
+                .await?;
         }
         Ok(())
     }
 
-    pub async fn prev(
+    /// Move on once the current item is done playing, honoring the repeat
+    /// mode and the stop-after-current flag of the playlist.
+    pub async fn advance(
         self: &Arc<Self>,
         db_conn: &mut SqliteConnection,
         playlist_id: PlaylistId,
     ) -> ResponseResult<()> {
-        if let Some(current_item) = Self::get_current_item(db_conn, playlist_id)? {
-            if let Some(prev) = current_item.prev {
-                self.set_playlist_item_as_current(db_conn, Some(playlist_id), prev)
-                    .await?;
-            } else if let Some(item) =
-                query_playlist_from_id(db_conn, playlist_id)?.last_playlist_item
-            {
-                self.set_playlist_item_as_current(db_conn, Some(playlist_id), item)
-                    .await?;
-            }
+        let playlist = query_playlist_from_id(db_conn, playlist_id)?;
+        if playlist.stop_after_current {
+            update_playlist_stop_after_current(db_conn, playlist_id, false)?;
+            self.metadata_changed(playlist_id).await;
+            self.pause(playlist_id).await;
+        } else if let (RepeatMode::One, Some(item_id)) =
+            (playlist.repeat_mode, playlist.current_item)
+        {
+            self.set_playlist_item_as_current(db_conn, Some(playlist_id), item_id)
+                .await?;
+        } else if !self.next(db_conn, playlist_id).await? {
+            // reached the end of a playlist that does not repeat
+            self.pause(playlist_id).await;
         }
         Ok(())
     }
@@ -711,7 +1048,8 @@ impl AppState {
                     .map(|sockets| sockets.socket_done(socket_id))
                     .unwrap_or_default()
                 {
-                    self.next(&mut db_conn, playlist_id).await?;
+                    end_play(&mut db_conn, playlist_id, true)?;
+                    self.advance(&mut db_conn, playlist_id).await?;
                 }
             }
             "play" => self.play(playlist_id).await,
@@ -740,6 +1078,7 @@ impl AppState {
         let body = match medias {
             MediaOrMediaList::Media(media) => media.display_string(),
             MediaOrMediaList::MediaList(media_list) => media_list.display_string(),
+            MediaOrMediaList::Medias(medias) => format!("{} medias", medias.len()),
         };
         let arc_self = self.clone();
         let playlist_title = playlist.title.clone();
@@ -805,3 +1144,171 @@ impl AppState {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::{
+        history::{query_play_history, HistoryFilter},
+        media::{insert_media, insert_media_list, NewMedia},
+        playlist::update_playlist_repeat_mode,
+        test_utils::{playlist_with_medias, TestDb},
+    };
+
+    fn current_item(db_conn: &mut SqliteConnection, playlist_id: PlaylistId) -> PlaylistItemId {
+        query_playlist_from_id(db_conn, playlist_id)
+            .unwrap()
+            .current_item
+            .unwrap()
+    }
+
+    fn plays(db_conn: &mut SqliteConnection, playlist_id: PlaylistId) -> Vec<PlaylistItemId> {
+        let filter = HistoryFilter {
+            playlist_id: Some(playlist_id),
+            ..Default::default()
+        };
+        query_play_history(db_conn, &filter, 0, 100)
+            .unwrap()
+            .into_iter()
+            .filter_map(|(entry, _)| entry.playlist_item_id)
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn repeat_one_replays_current_item() {
+        let db = TestDb::new();
+        let app = AppState::detached(db.pool.clone());
+        let mut db_conn = db.pool.get().unwrap();
+        let (playlist, ids) = playlist_with_medias(&mut db_conn, &[10; 3]).await;
+        update_playlist_current_item(&mut db_conn, playlist.id, Some(ids[1])).unwrap();
+        update_playlist_repeat_mode(&mut db_conn, playlist.id, RepeatMode::One).unwrap();
+
+        app.advance(&mut db_conn, playlist.id).await.unwrap();
+        app.advance(&mut db_conn, playlist.id).await.unwrap();
+        assert_eq!(current_item(&mut db_conn, playlist.id), ids[1]);
+        assert_eq!(plays(&mut db_conn, playlist.id), [ids[1], ids[1]]);
+    }
+
+    #[tokio::test]
+    async fn repeat_none_stops_at_last_item() {
+        let db = TestDb::new();
+        let app = AppState::detached(db.pool.clone());
+        let mut db_conn = db.pool.get().unwrap();
+        let (playlist, ids) = playlist_with_medias(&mut db_conn, &[10; 3]).await;
+        update_playlist_current_item(&mut db_conn, playlist.id, Some(ids[1])).unwrap();
+        update_playlist_repeat_mode(&mut db_conn, playlist.id, RepeatMode::None).unwrap();
+
+        app.advance(&mut db_conn, playlist.id).await.unwrap();
+        assert_eq!(current_item(&mut db_conn, playlist.id), ids[2]);
+        app.advance(&mut db_conn, playlist.id).await.unwrap();
+        assert_eq!(current_item(&mut db_conn, playlist.id), ids[2]);
+        assert_eq!(plays(&mut db_conn, playlist.id), [ids[2]]);
+    }
+
+    #[tokio::test]
+    async fn stop_after_current_halts_once() {
+        let db = TestDb::new();
+        let app = AppState::detached(db.pool.clone());
+        let mut db_conn = db.pool.get().unwrap();
+        let (playlist, ids) = playlist_with_medias(&mut db_conn, &[10; 3]).await;
+        update_playlist_current_item(&mut db_conn, playlist.id, Some(ids[0])).unwrap();
+        update_playlist_stop_after_current(&mut db_conn, playlist.id, true).unwrap();
+
+        app.advance(&mut db_conn, playlist.id).await.unwrap();
+        assert_eq!(current_item(&mut db_conn, playlist.id), ids[0]);
+        assert!(plays(&mut db_conn, playlist.id).is_empty());
+        assert!(
+            !query_playlist_from_id(&mut db_conn, playlist.id)
+                .unwrap()
+                .stop_after_current
+        );
+
+        app.advance(&mut db_conn, playlist.id).await.unwrap();
+        assert_eq!(current_item(&mut db_conn, playlist.id), ids[1]);
+    }
+
+    #[tokio::test]
+    async fn video_in_list_urls_hit_the_cache() {
+        let db = TestDb::new();
+        let app = AppState::detached(db.pool.clone());
+        let mut db_conn = db.pool.get().unwrap();
+        let video = insert_media(
+            &mut db_conn,
+            NewMedia {
+                title: "video".into(),
+                artist: "channel".into(),
+                duration: Some(10),
+                url: "https://youtu.be/dQw4w9WgXcQ".into(),
+                media_type: "yt".into(),
+                album: None,
+                track: None,
+                release_date: None,
+                extractor: None,
+            },
+        )
+        .unwrap();
+        let list = insert_media_list(
+            &mut db_conn,
+            NewMediaList {
+                title: "list".into(),
+                artist: "channel".into(),
+                url: "https://youtube.com/playlist?list=PLrAXtmErZgOeiKm4sgNOknGvNjby9efdf".into(),
+                total_duration: 10,
+            },
+            &[video.id],
+        )
+        .unwrap();
+
+        // yt-dlp is never run, the cached media and list are returned
+        let url =
+            "https://www.youtube.com/watch?v=dQw4w9WgXcQ&list=PLrAXtmErZgOeiKm4sgNOknGvNjby9efdf";
+        assert!(matches!(
+            app.fetch_medias(&mut db_conn, url, false, None).await,
+            Ok(MediaOrMediaList::Media(media)) if media.id == video.id
+        ));
+        assert!(matches!(
+            app.fetch_medias(&mut db_conn, url, true, None).await,
+            Ok(MediaOrMediaList::MediaList(media_list)) if media_list.id == list.id
+        ));
+    }
+
+    #[tokio::test]
+    async fn unresolvable_list_entries_are_skipped() {
+        let db = TestDb::new();
+        let app = AppState::detached(db.pool.clone());
+        let mut db_conn = db.pool.get().unwrap();
+        let listed = MediaListEntry {
+            url: "file:///nonexistent/listed.mp3".into(),
+            metadata: Some(NewMedia {
+                title: "listed".into(),
+                artist: "artist".into(),
+                duration: Some(10),
+                url: "file:///nonexistent/listed.mp3".into(),
+                media_type: "local".into(),
+                album: None,
+                track: None,
+                release_date: None,
+                extractor: None,
+            }),
+        };
+        let missing = || MediaListEntry::from("file:///nonexistent/missing.mp3".to_owned());
+
+        let medias = app
+            .resolve_media_list_entries(&mut db_conn, "test", vec![missing(), listed], None)
+            .await
+            .unwrap();
+        assert_eq!(
+            medias.iter().map(|m| m.title.as_str()).collect::<Vec<_>>(),
+            ["listed"]
+        );
+
+        // nothing to keep
+        assert!(matches!(
+            app.resolve_media_list_entries(&mut db_conn, "test", vec![missing()], None)
+                .await,
+            Err(FetchMediaError::ResolveError(
+                MediaResolveError::MediaNotFound
+            ))
+        ));
+    }
+}
@@ -0,0 +1,212 @@
+use std::{fmt::Write, sync::Arc};
+
+use super::{
+    app::{AppRouter, AppState},
+    ResponseResult,
+};
+use crate::db::{
+    media::{query_media_with_id, Media},
+    playlist::{query_playlist_from_id, Playlist, PlaylistId},
+    playlist_item::query_playlist_items_in_order,
+};
+use axum::{
+    extract::{Path, State},
+    http::header::{CONTENT_DISPOSITION, CONTENT_TYPE},
+    response::{IntoResponse, Response},
+    routing::get,
+};
+use diesel::SqliteConnection;
+use quick_xml::escape::escape;
+
+pub fn export_router() -> AppRouter {
+    AppRouter::new()
+        .route("/playlist/:id/export.m3u8", get(export_m3u8))
+        .route("/playlist/:id/export.xspf", get(export_xspf))
+}
+
+fn query_playlist_with_medias(
+    db_conn: &mut SqliteConnection,
+    playlist_id: PlaylistId,
+) -> ResponseResult<(Playlist, Vec<Media>)> {
+    let playlist = query_playlist_from_id(db_conn, playlist_id)?;
+    let medias = query_playlist_items_in_order(db_conn, playlist.id, playlist.first_playlist_item)?
+        .into_iter()
+        .map(|item| query_media_with_id(db_conn, item.media_id))
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok((playlist, medias))
+}
+
+fn attachment(
+    playlist: &Playlist,
+    content_type: &'static str,
+    extension: &str,
+    body: String,
+) -> Response {
+    let file_name: String = playlist
+        .title
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || " -_.()".contains(c) {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect();
+    let disposition = format!("attachment; filename=\"{file_name}.{extension}\"");
+    (
+        [
+            (CONTENT_TYPE, content_type.to_owned()),
+            (CONTENT_DISPOSITION, disposition),
+        ],
+        body,
+    )
+        .into_response()
+}
+
+/// Line breaks would start a new entry in M3U files.
+fn single_line(s: &str) -> String {
+    s.replace(['\r', '\n'], " ")
+}
+
+async fn export_m3u8(
+    Path(playlist_id): Path<i32>,
+    State(app): State<Arc<AppState>>,
+) -> ResponseResult<Response> {
+    let mut db_conn = app.acquire_db_connection()?;
+    let (playlist, medias) = query_playlist_with_medias(&mut db_conn, PlaylistId(playlist_id))?;
+    let mut body = String::from("#EXTM3U\n");
+    writeln!(body, "#PLAYLIST:{}", single_line(&playlist.title)).unwrap();
+    for media in medias {
+        writeln!(
+            body,
+            "#EXTINF:{},{} - {}\n{}",
+            media.duration.map(|d| d.whole_seconds()).unwrap_or(-1),
+            single_line(media.display_artist()),
+            single_line(media.display_title()),
+            media.url,
+        )
+        .unwrap();
+    }
+    Ok(attachment(&playlist, "audio/x-mpegurl", "m3u8", body))
+}
+
+async fn export_xspf(
+    Path(playlist_id): Path<i32>,
+    State(app): State<Arc<AppState>>,
+) -> ResponseResult<Response> {
+    let mut db_conn = app.acquire_db_connection()?;
+    let (playlist, medias) = query_playlist_with_medias(&mut db_conn, PlaylistId(playlist_id))?;
+    let mut body = String::from(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<playlist version=\"1\" xmlns=\"http://xspf.org/ns/0/\">\n",
+    );
+    writeln!(body, "  <title>{}</title>", escape(&playlist.title)).unwrap();
+    body.push_str("  <trackList>\n");
+    for media in medias {
+        body.push_str("    <track>\n");
+        writeln!(body, "      <location>{}</location>", escape(&media.url)).unwrap();
+        writeln!(
+            body,
+            "      <title>{}</title>",
+            escape(media.display_title())
+        )
+        .unwrap();
+        writeln!(
+            body,
+            "      <creator>{}</creator>",
+            escape(media.display_artist())
+        )
+        .unwrap();
+        if let Some(album) = &media.album {
+            writeln!(body, "      <album>{}</album>", escape(album)).unwrap();
+        }
+        if let Some(track) = media.track {
+            writeln!(body, "      <trackNum>{track}</trackNum>").unwrap();
+        }
+        if let Some(duration) = media.duration {
+            writeln!(
+                body,
+                "      <duration>{}</duration>",
+                duration.whole_milliseconds()
+            )
+            .unwrap();
+        }
+        body.push_str("    </track>\n");
+    }
+    body.push_str("  </trackList>\n</playlist>\n");
+    Ok(attachment(&playlist, "application/xspf+xml", "xspf", body))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::{
+        media::update_media_alt_data,
+        playlist::rename_playlist,
+        playlist_item::{move_playlist_items, query_playlist_item, MoveTarget},
+        test_utils::{playlist_with_medias, TestDb},
+    };
+
+    async fn body(response: Response) -> String {
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        String::from_utf8(body.to_vec()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn playlists_are_exported_in_order() {
+        let db = TestDb::new();
+        let app = AppState::detached(db.pool.clone());
+        let mut db_conn = db.pool.get().unwrap();
+        let (playlist, ids) = playlist_with_medias(&mut db_conn, &[61, 5]).await;
+        rename_playlist(&mut db_conn, playlist.id, "Rock & <Roll>").unwrap();
+        let urls = [
+            (ids[0], "Tom & Jerry", "Someone"),
+            (ids[1], "\"Second\"", "<Band>"),
+        ]
+        .map(|(item_id, title, artist)| {
+            let media_id = query_playlist_item(&mut db_conn, item_id).unwrap().media_id;
+            update_media_alt_data(&mut db_conn, media_id, title, artist)
+                .unwrap()
+                .url
+        });
+        move_playlist_items(&mut db_conn, playlist.id, &[ids[1]], MoveTarget::Start).unwrap();
+
+        let m3u8 = body(
+            export_m3u8(Path(playlist.id.0), State(app.clone()))
+                .await
+                .unwrap(),
+        )
+        .await;
+        assert_eq!(
+            m3u8,
+            format!(
+                "#EXTM3U\n#PLAYLIST:Rock & <Roll>\n\
+                 #EXTINF:5,<Band> - \"Second\"\n{}\n\
+                 #EXTINF:61,Someone - Tom & Jerry\n{}\n",
+                urls[1], urls[0]
+            )
+        );
+
+        let xspf = body(export_xspf(Path(playlist.id.0), State(app)).await.unwrap()).await;
+        assert!(xspf.contains("<title>Rock &amp; &lt;Roll&gt;</title>"));
+        let tracks = xspf
+            .split("<track>")
+            .skip(1)
+            .map(|track| {
+                ["title", "creator", "duration"].map(|tag| {
+                    let (_, rest) = track.split_once(&format!("<{tag}>")).unwrap();
+                    rest.split_once(&format!("</{tag}>")).unwrap().0.to_owned()
+                })
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            tracks,
+            [
+                ["&quot;Second&quot;", "&lt;Band&gt;", "5000"],
+                ["Tom &amp; Jerry", "Someone", "61000"],
+            ]
+        );
+    }
+}
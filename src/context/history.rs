@@ -0,0 +1,102 @@
+use std::sync::Arc;
+
+use super::{
+    app::{AppRouter, AppState},
+    ResponseError, ResponseResult,
+};
+use crate::db::{
+    history::{query_play_history, HistoryFilter, PlayHistoryEntry},
+    media::Media,
+    playlist::PlaylistId,
+};
+use axum::{
+    extract::{Query, State},
+    routing::get,
+    Json,
+};
+use serde::{Deserialize, Serialize};
+use time::{format_description::well_known::Iso8601, Date};
+
+pub fn history_router() -> AppRouter {
+    AppRouter::new().route("/api/history", get(history_list))
+}
+
+/// Dates are formatted as `YYYY-MM-DD`. Empty values are ignored, so that
+/// the query of an unfilled form is still valid.
+#[derive(Deserialize)]
+pub(super) struct HistoryQuery {
+    #[serde(default)]
+    pub playlist: Option<String>,
+    #[serde(default)]
+    pub from: Option<String>,
+    #[serde(default)]
+    pub to: Option<String>,
+    #[serde(default)]
+    pub offset: i64,
+    /// Defaults to [`HistoryQuery::DEFAULT_LIMIT`].
+    #[serde(default)]
+    pub limit: Option<i64>,
+}
+
+fn non_empty(value: &Option<String>) -> Option<&str> {
+    value.as_deref().map(str::trim).filter(|v| !v.is_empty())
+}
+
+impl HistoryQuery {
+    pub const DEFAULT_LIMIT: i64 = 100;
+
+    pub fn limit(&self) -> i64 {
+        self.limit.unwrap_or(Self::DEFAULT_LIMIT)
+    }
+
+    pub fn filter(&self) -> ResponseResult<HistoryFilter> {
+        let parse_date = |value: &Option<String>| {
+            non_empty(value)
+                .map(|date| {
+                    Date::parse(date, &Iso8601::DATE).map_err(|e| {
+                        ResponseError::InvalidRequest(format!("Invalid date {date}: {e}").into())
+                    })
+                })
+                .transpose()
+        };
+        let playlist_id = non_empty(&self.playlist)
+            .map(|id| {
+                id.parse().map(PlaylistId).map_err(|_| {
+                    ResponseError::InvalidRequest(format!("Invalid playlist ID: {id}").into())
+                })
+            })
+            .transpose()?;
+        Ok(HistoryFilter {
+            playlist_id,
+            from: parse_date(&self.from)?,
+            to: parse_date(&self.to)?,
+        })
+    }
+}
+
+#[derive(Serialize)]
+struct HistoryItem {
+    #[serde(flatten)]
+    entry: PlayHistoryEntry,
+    media: Media,
+}
+
+async fn history_list(
+    Query(query): Query<HistoryQuery>,
+    State(app): State<Arc<AppState>>,
+) -> ResponseResult<Json<Vec<HistoryItem>>> {
+    let filter = query.filter()?;
+    let mut db_conn = app.acquire_db_connection()?;
+    let history = query_play_history(
+        &mut db_conn,
+        &filter,
+        query.offset.max(0),
+        query.limit().clamp(1, 1000),
+    )?;
+    Ok(Json(
+        history
+            .into_iter()
+            .map(|(entry, media)| HistoryItem { entry, media })
+            .collect(),
+    ))
+}
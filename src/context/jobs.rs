@@ -0,0 +1,241 @@
+use std::{collections::HashMap, sync::Arc, time::Duration};
+
+use super::{
+    app::{AppRouter, AppState, ProgressReporter},
+    ResponseError, ResponseResult,
+};
+use crate::db::{
+    job::{
+        claim_pending_job, query_job, query_jobs, reset_running_jobs, transition_job, Job, JobId,
+        JobStatus,
+    },
+    playlist::PlaylistId,
+};
+use anyhow::Result;
+use axum::{
+    extract::{Path, Query, State},
+    routing::{get, patch},
+    Json,
+};
+use lazy_static::lazy_static;
+use serde::Deserialize;
+use tokio::{
+    sync::{Mutex, Notify},
+    task::AbortHandle,
+};
+
+pub fn jobs_router() -> AppRouter {
+    AppRouter::new()
+        .route("/jobs", get(jobs_list))
+        .route("/job/:id", get(job_get))
+        .route("/job/:id/cancel", patch(job_cancel))
+        .route("/job/:id/retry", patch(job_retry))
+}
+
+lazy_static! {
+    static ref JOB_WORKERS: usize = std::env::var("JOB_WORKERS")
+        .ok()
+        .and_then(|env| env.parse::<usize>().ok())
+        .filter(|workers| *workers > 0)
+        .unwrap_or(2);
+}
+
+/// Wakes up the job workers and keeps track of the running jobs, so that
+/// they can be cancelled.
+#[derive(Default)]
+pub struct JobQueue {
+    notify: Notify,
+    running: Mutex<HashMap<JobId, AbortHandle>>,
+}
+
+impl JobQueue {
+    /// Spawn the worker pool. Jobs left running by a previous instance are
+    /// queued again.
+    pub fn start(app: &Arc<AppState>) -> Result<()> {
+        let mut db_conn = app.acquire_db_connection()?;
+        let reset = reset_running_jobs(&mut db_conn)?;
+        if reset > 0 {
+            tracing::info!("requeued {reset} interrupted jobs");
+        }
+        for _ in 0..*JOB_WORKERS {
+            tokio::spawn(worker(app.clone()));
+        }
+        Ok(())
+    }
+
+    pub fn notify(&self) {
+        self.notify.notify_one();
+    }
+
+    async fn cancel(&self, job_id: JobId) {
+        if let Some(handle) = self.running.lock().await.remove(&job_id) {
+            handle.abort();
+        }
+    }
+}
+
+async fn worker(app: Arc<AppState>) {
+    loop {
+        let job = app
+            .acquire_db_connection()
+            .map_err(anyhow::Error::from)
+            .and_then(|mut db_conn| Ok(claim_pending_job(&mut db_conn)?));
+        match job {
+            Ok(Some(job)) => run_job(&app, job).await,
+            Ok(None) => app.jobs().notify.notified().await,
+            Err(e) => {
+                tracing::warn!("unable to fetch pending jobs: {e}");
+                tokio::time::sleep(Duration::from_secs(5)).await;
+            }
+        }
+    }
+}
+
+async fn run_job(app: &Arc<AppState>, job: Job) {
+    let job_id = job.id;
+    let progress = ProgressReporter::new(job.playlist_id);
+    tracing::info!("running job {job_id}: adding {}", job.url);
+    let task = tokio::spawn({
+        let app = app.clone();
+        async move { process_job(&app, &job, progress).await }
+    });
+    app.jobs()
+        .running
+        .lock()
+        .await
+        .insert(job_id, task.abort_handle());
+    let result = task.await;
+    app.jobs().running.lock().await.remove(&job_id);
+
+    let (status, error) = match result {
+        Ok(Ok(())) => (JobStatus::Done, None),
+        Ok(Err(e)) => (JobStatus::Failed, Some(e.to_string())),
+        // the status was already updated by whoever cancelled the job
+        Err(e) if e.is_cancelled() => return,
+        Err(e) => (JobStatus::Failed, Some(format!("job panicked: {e}"))),
+    };
+    if let Some(error) = &error {
+        tracing::warn!("job {job_id} failed: {error}");
+        app.report_progress(Some(progress), &format!("job {job_id} failed: {error}"))
+            .await;
+    }
+    app.acquire_db_connection()
+        .map_err(anyhow::Error::from)
+        .and_then(|mut db_conn| {
+            Ok(transition_job(
+                &mut db_conn,
+                job_id,
+                &[JobStatus::Running],
+                status,
+                error.as_deref(),
+            )?)
+        })
+        .map_err(|e| tracing::warn!("unable to update status of job {job_id}: {e}"))
+        .ok();
+}
+
+async fn process_job(app: &Arc<AppState>, job: &Job, progress: ProgressReporter) -> Result<()> {
+    let mut db_conn = app.acquire_db_connection()?;
+    let medias = app
+        .fetch_medias(&mut db_conn, &job.url, job.prefer_list, Some(progress))
+        .await?;
+    // The job is done as soon as its medias are added, so that it can no
+    // longer be cancelled. A job cancelled before that adds nothing.
+    app.add_to_playlist_if(
+        &mut db_conn,
+        job.playlist_id,
+        job.position,
+        &medias,
+        |db_conn| {
+            Ok(transition_job(
+                db_conn,
+                job.id,
+                &[JobStatus::Running],
+                JobStatus::Done,
+                None,
+            )?
+            .is_some())
+        },
+    )
+    .await?;
+    Ok(())
+}
+
+#[derive(Deserialize)]
+struct JobsQuery {
+    playlist: Option<i32>,
+    #[serde(default = "default_jobs_limit")]
+    limit: i64,
+}
+
+fn default_jobs_limit() -> i64 {
+    50
+}
+
+async fn jobs_list(
+    Query(query): Query<JobsQuery>,
+    State(app): State<Arc<AppState>>,
+) -> ResponseResult<Json<Vec<Job>>> {
+    let mut db_conn = app.acquire_db_connection()?;
+    let jobs = query_jobs(
+        &mut db_conn,
+        query.playlist.map(PlaylistId),
+        query.limit.clamp(1, 1000),
+    )?;
+    Ok(Json(jobs))
+}
+
+async fn job_get(
+    Path(job_id): Path<i32>,
+    State(app): State<Arc<AppState>>,
+) -> ResponseResult<Json<Job>> {
+    let mut db_conn = app.acquire_db_connection()?;
+    Ok(Json(query_job(&mut db_conn, JobId(job_id))?))
+}
+
+async fn job_cancel(
+    Path(job_id): Path<i32>,
+    State(app): State<Arc<AppState>>,
+) -> ResponseResult<Json<Job>> {
+    let job_id = JobId(job_id);
+    let mut db_conn = app.acquire_db_connection()?;
+    let Some(cancelled) = transition_job(
+        &mut db_conn,
+        job_id,
+        &[JobStatus::Pending, JobStatus::Running],
+        JobStatus::Cancelled,
+        None,
+    )?
+    else {
+        // the job may have been done in the meantime
+        let job = query_job(&mut db_conn, job_id)?;
+        return Err(ResponseError::UnprocessableEntity(
+            format!("Job {job_id} is already {}", job.status.as_str()).into(),
+        ));
+    };
+    app.jobs().cancel(job_id).await;
+    Ok(Json(cancelled))
+}
+
+async fn job_retry(
+    Path(job_id): Path<i32>,
+    State(app): State<Arc<AppState>>,
+) -> ResponseResult<Json<Job>> {
+    let job_id = JobId(job_id);
+    let mut db_conn = app.acquire_db_connection()?;
+    let job = query_job(&mut db_conn, job_id)?;
+    let retried = transition_job(
+        &mut db_conn,
+        job_id,
+        &[JobStatus::Failed, JobStatus::Cancelled],
+        JobStatus::Pending,
+        None,
+    )?
+    .ok_or_else(|| {
+        ResponseError::UnprocessableEntity(
+            format!("Job {job_id} is {}", job.status.as_str()).into(),
+        )
+    })?;
+    app.jobs().notify();
+    Ok(Json(retried))
+}
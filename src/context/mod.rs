@@ -13,9 +13,13 @@ use std::{borrow::Cow, convert::Infallible};
 use thiserror::Error;
 
 pub mod app;
+mod export;
+mod history;
+mod jobs;
 mod playlist;
 mod ssr;
 mod static_files;
+mod stats;
 mod ws;
 
 pub async fn create_app_router() -> Result<Router> {
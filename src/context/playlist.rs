@@ -1,35 +1,42 @@
 use super::{
-    app::{AppRouter, AppState, FetchMediaError},
+    app::{total_duration_of, AppRouter, AppState, FetchMediaError},
     ResponseError, ResponseResult,
 };
-use crate::{
-    db::{
-        media::{query_media_with_id, replace_media_metadata, update_media_alt_data, MediaId},
-        playlist::{
-            append_to_playlist, create_empty_playlist, delete_playlist, query_playlist_from_id,
-            rename_playlist, update_playlist, update_playlist_first_item,
-            update_playlist_last_item, PlaylistId,
-        },
-        playlist_item::{
-            playlist_items_with_media_id, query_playlist_item, remove_playlist_item,
-            update_playlist_item_next_id, update_playlist_item_prev_and_next_id,
-            update_playlist_item_prev_id, PlaylistItemId,
-        },
-        ResourceQueryResult,
+use crate::db::{
+    history::end_play,
+    immediate_transaction,
+    integrity::{fsck_playlist, IntegrityReport},
+    job::{insert_job, NewJob},
+    media::{
+        query_media_list_medias, query_media_list_with_id, query_media_lists_with_media,
+        query_media_with_id, replace_media_metadata, search_medias, update_media_alt_data,
+        update_media_list_medias, Media, MediaId, MediaList, MediaListId, MediaOrMediaList,
+        NewMediaList,
+    },
+    playlist::{
+        create_empty_playlist, delete_playlist, query_playlist_from_id, rename_playlist,
+        sync_media_list_into_playlist, update_playlist, update_playlist_repeat_mode,
+        update_playlist_stop_after_current, AddPosition, PlaylistId, RepeatMode,
+    },
+    playlist_item::{
+        move_playlist_items, move_playlist_items_down, move_playlist_items_up,
+        playlist_items_with_media_id, playlist_items_with_media_list_id, remove_playlist_item,
+        shuffle_playlist_items, MoveTarget, PlaylistItemId,
     },
-    resolvers::resolve_media,
+    shuffle::set_playlist_shuffle,
+    ResourceQueryResult,
 };
 use anyhow::anyhow;
 use axum::{
     body::Body,
     extract::{Path, Query, State},
     http::{HeaderMap, Request, StatusCode},
-    response::{AppendHeaders, IntoResponse, Response},
+    response::{AppendHeaders, IntoResponse, Redirect, Response},
     routing::{delete, get, patch, post, put},
     Form, Json, Router,
 };
-use diesel::SqliteConnection;
-use serde::Deserialize;
+use rand::{rngs::StdRng, SeedableRng};
+use serde::{Deserialize, Serialize};
 use std::{
     collections::{hash_map::Entry, HashMap, HashSet},
     sync::Arc,
@@ -42,12 +49,20 @@ use url::Url;
 pub fn playlist_router() -> AppRouter {
     Router::new()
         .route("/playlist/:id/add", post(playlist_add))
+        .route("/playlist/:id/addmedia", post(playlist_add_media))
+        .route("/library/add", post(library_add))
         .route("/playlist/:id/play", post(playlist_play))
         .route("/playlist/new", put(playlist_new))
         .route("/playlist/:id/rename", patch(playlist_rename))
         .route("/playlist/:id/rename-norefresh", patch(playlist_rename))
         .route("/playlist/:id/next", patch(playlist_next))
         .route("/playlist/:id/prev", patch(playlist_prev))
+        .route("/playlist/:id/repeat", patch(playlist_repeat))
+        .route(
+            "/playlist/:id/stop-after-current",
+            patch(playlist_stop_after_current),
+        )
+        .route("/playlist/:id/shuffle-play", patch(playlist_shuffle_play))
         .route("/playlist/:id/servermedia", get(legacy_servermedia))
         .route("/servermedia/:id", get(servermedia))
         .route("/playlist/goto/:id", patch(playlist_goto))
@@ -56,55 +71,114 @@ pub fn playlist_router() -> AppRouter {
         .route("/playlist/:id/deletelist", delete(playlist_delete_list))
         .route("/playlist/:id/up", patch(playlist_move_up))
         .route("/playlist/:id/down", patch(playlist_move_down))
+        .route("/playlist/:id/move", patch(playlist_move))
+        .route("/playlist/:id/shuffle", post(playlist_shuffle))
+        .route(
+            "/playlist/:id/fsck",
+            get(playlist_fsck).post(playlist_fsck_repair),
+        )
+        .route("/api/search", get(search))
         .route("/media/:id/update", patch(update_media))
+        .route("/media/:id/lists", get(media_lists_with_media))
+        .route("/medialist/:id/medias", get(media_list_medias))
+        .route("/medialist/:id/update", patch(update_media_list))
         .route("/media/:id/metadata/edit", patch(update_media_metadata))
 }
 
-#[derive(Debug, Deserialize)]
-#[serde(rename_all = "kebab-case")]
-enum AddPosition {
-    QueueNext,
-    AddToStart,
-    AddToEnd,
-}
-
-impl Default for AddPosition {
-    fn default() -> Self {
-        Self::QueueNext
-    }
-}
-
 #[derive(Deserialize)]
 struct PlaylistArgInfo {
     position: AddPosition,
     url: String,
+    /// Checkbox for urls referencing both a media and a media list.
+    #[serde(default, rename = "prefer-list")]
+    prefer_list: Option<String>,
 }
 
 async fn playlist_add(
     State(app): State<Arc<AppState>>,
     Path(playlist_id): Path<i32>,
     Form(info): Form<PlaylistArgInfo>,
+) -> ResponseResult<String> {
+    let mut db_conn = app.acquire_db_connection()?;
+    let playlist = query_playlist_from_id(&mut db_conn, PlaylistId(playlist_id))?;
+    let job = insert_job(
+        &mut db_conn,
+        NewJob {
+            playlist_id: playlist.id,
+            url: info.url.trim(),
+            position: info.position,
+            prefer_list: info.prefer_list.is_some(),
+        },
+    )?;
+    app.jobs().notify();
+    Ok(format!("Job {} queued", job.id))
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "kebab-case")]
+struct PlaylistAddMediaInfo {
+    position: AddPosition,
+    media_id: MediaId,
+}
+
+/// Add an already cached media, without resolving it again.
+async fn playlist_add_media(
+    State(app): State<Arc<AppState>>,
+    Path(playlist_id): Path<i32>,
+    Form(info): Form<PlaylistAddMediaInfo>,
 ) -> ResponseResult<()> {
-    let playlist_id = PlaylistId(playlist_id);
     let mut db_conn = app.acquire_db_connection()?;
-    let PlaylistArgInfo { position, url } = info;
-    let medias = app.fetch_medias(&mut db_conn, &url).await?;
-    let playlist = query_playlist_from_id(&mut db_conn, playlist_id)?;
-    let pivot = match position {
-        AddPosition::AddToStart => None,
-        AddPosition::QueueNext => playlist.current_item,
-        AddPosition::AddToEnd => playlist.last_playlist_item,
-    };
-    let total_duration = medias.total_duration();
-    let media_ids = medias.media_ids();
-    let item_ids =
-        append_to_playlist(&mut db_conn, playlist.id, pivot, &media_ids, total_duration)?;
-    #[allow(unused)]
-    if let Some(first_item_id) = item_ids.first() {
-        #[cfg(feature = "notifications")]
-        app.notify_playlist_add(&playlist, &medias, *first_item_id);
-        app.refresh_playlist(playlist.id).await;
+    let media = query_media_with_id(&mut db_conn, info.media_id)?;
+    app.add_to_playlist(
+        &mut db_conn,
+        PlaylistId(playlist_id),
+        info.position,
+        &media.into(),
+    )
+    .await?;
+    Ok(())
+}
+
+/// Add the medias selected in the library page, in the order they are listed.
+async fn library_add(
+    State(app): State<Arc<AppState>>,
+    Form(fields): Form<Vec<(String, String)>>,
+) -> ResponseResult<()> {
+    let mut playlist_id = None;
+    let mut position = AddPosition::default();
+    let mut media_ids = Vec::new();
+    for (key, value) in fields.iter() {
+        match key.as_str() {
+            "playlist" => playlist_id = value.parse::<PlaylistId>().ok(),
+            "position" => {
+                position = value.parse().map_err(|e: anyhow::Error| {
+                    ResponseError::InvalidRequest(e.to_string().into())
+                })?
+            }
+            key => media_ids.extend(
+                key.strip_prefix("media-")
+                    .and_then(|id| id.parse::<MediaId>().ok()),
+            ),
+        }
+    }
+    let playlist_id =
+        playlist_id.ok_or(ResponseError::InvalidRequest("no playlist selected".into()))?;
+    if media_ids.is_empty() {
+        return Err(ResponseError::InvalidRequest("no media selected".into()));
     }
+
+    let mut db_conn = app.acquire_db_connection()?;
+    let medias = media_ids
+        .into_iter()
+        .map(|media_id| query_media_with_id(&mut db_conn, media_id))
+        .collect::<ResourceQueryResult<Vec<_>>>()?;
+    app.add_to_playlist(
+        &mut db_conn,
+        playlist_id,
+        position,
+        &MediaOrMediaList::Medias(medias),
+    )
+    .await?;
     Ok(())
 }
 
@@ -179,8 +253,14 @@ async fn playlist_delete_list(
     Path(playlist_id): Path<i32>,
     State(app): State<Arc<AppState>>,
 ) -> ResponseResult<Response> {
+    let playlist_id = PlaylistId(playlist_id);
     let mut db_conn = app.acquire_db_connection()?;
-    delete_playlist(&mut db_conn, PlaylistId(playlist_id))?;
+    // the history outlives the playlist, but should not keep playing it
+    end_play(&mut db_conn, playlist_id, false)?;
+    delete_playlist(&mut db_conn, playlist_id)?;
+    if app.get_current_playlist().await == Some(playlist_id) {
+        app.set_current_playlist(None).await?;
+    }
     Ok(redirect("/watch"))
 }
 
@@ -202,6 +282,57 @@ async fn playlist_prev(
     Ok("a".into_response())
 }
 
+#[derive(Deserialize)]
+struct PlaylistRepeatQuery {
+    mode: RepeatMode,
+}
+
+async fn playlist_repeat(
+    Path(playlist_id): Path<i32>,
+    Query(query): Query<PlaylistRepeatQuery>,
+    State(app): State<Arc<AppState>>,
+) -> ResponseResult<()> {
+    let playlist_id = PlaylistId(playlist_id);
+    let mut db_conn = app.acquire_db_connection()?;
+    update_playlist_repeat_mode(&mut db_conn, playlist_id, query.mode)?;
+    app.metadata_changed(playlist_id).await;
+    Ok(())
+}
+
+#[derive(Deserialize)]
+struct PlaylistStopAfterCurrentQuery {
+    enabled: bool,
+}
+
+async fn playlist_stop_after_current(
+    Path(playlist_id): Path<i32>,
+    Query(query): Query<PlaylistStopAfterCurrentQuery>,
+    State(app): State<Arc<AppState>>,
+) -> ResponseResult<()> {
+    let playlist_id = PlaylistId(playlist_id);
+    let mut db_conn = app.acquire_db_connection()?;
+    update_playlist_stop_after_current(&mut db_conn, playlist_id, query.enabled)?;
+    app.metadata_changed(playlist_id).await;
+    Ok(())
+}
+
+#[derive(Deserialize)]
+struct PlaylistShufflePlayQuery {
+    enabled: bool,
+}
+
+async fn playlist_shuffle_play(
+    Path(playlist_id): Path<i32>,
+    Query(query): Query<PlaylistShufflePlayQuery>,
+    State(app): State<Arc<AppState>>,
+) -> ResponseResult<()> {
+    let playlist_id = PlaylistId(playlist_id);
+    let mut db_conn = app.acquire_db_connection()?;
+    set_playlist_shuffle(&mut db_conn, playlist_id, query.enabled)?;
+    app.metadata_changed(playlist_id).await;
+    Ok(())
+}
+
 async fn legacy_servermedia(
     Path(playlist_id): Path<i32>,
     State(app): State<Arc<AppState>>,
@@ -210,14 +341,7 @@ async fn legacy_servermedia(
     let playlist_id = PlaylistId(playlist_id);
     let mut db_conn = app.acquire_db_connection()?;
     if let Some(media) = AppState::get_current_media(&mut db_conn, playlist_id).await? {
-        if media.media_type == "local" {
-            let path = Url::parse(&media.url)
-                .map_err(|e| anyhow!("Invalid URL: {e}"))?
-                .to_file_path()
-                .map_err(|_| anyhow!("Unable to convert local URL to path"))?;
-            tracing::info!("transfering file: {}", path.display());
-            return Ok(ServeFile::new(path).oneshot(request).await?.into_response());
-        }
+        return serve_media(&app, &media, request).await;
     }
 
     Ok((StatusCode::NOT_FOUND, "Playlist not found").into_response())
@@ -232,16 +356,34 @@ async fn servermedia(
     let media_id = MediaId(media_id);
     let mut db_conn = app.acquire_db_connection()?;
     let media = query_media_with_id(&mut db_conn, media_id)?;
-    if media.media_type == "local" {
-        let path = Url::parse(&media.url)
-            .map_err(|e| anyhow!("Invalid URL: {e}"))?
-            .to_file_path()
-            .map_err(|_| anyhow!("Unable to convert local URL to path"))?;
-        tracing::info!("transfering file: {}", path.display());
-        return Ok(ServeFile::new(path).oneshot(request).await?.into_response());
-    }
+    serve_media(&app, &media, request).await
+}
 
-    Ok((StatusCode::NOT_FOUND, "Media not found").into_response())
+async fn serve_media(
+    app: &AppState,
+    media: &Media,
+    request: Request<Body>,
+) -> ResponseResult<Response> {
+    match media.media_type.as_str() {
+        "local" => {
+            let path = Url::parse(&media.url)
+                .map_err(|e| anyhow!("Invalid URL: {e}"))?
+                .to_file_path()
+                .map_err(|_| anyhow!("Unable to convert local URL to path"))?;
+            tracing::info!("transfering file: {}", path.display());
+            Ok(ServeFile::new(path).oneshot(request).await?.into_response())
+        }
+        "http" => Ok(Redirect::temporary(&media.url).into_response()),
+        "ytdl" => {
+            let direct_url = app
+                .direct_urls()
+                .get_or_resolve(media.id, &media.url)
+                .await
+                .map_err(|e| anyhow!("unable to resolve direct url of {}: {e}", media.url))?;
+            Ok(Redirect::temporary(&direct_url).into_response())
+        }
+        _ => Ok((StatusCode::NOT_FOUND, "Media not found").into_response()),
+    }
 }
 
 async fn playlist_goto(
@@ -275,15 +417,14 @@ async fn playlist_delete(
 ) -> ResponseResult<Response> {
     let playlist_id = PlaylistId(playlist_id);
     let mut db_conn = app.acquire_db_connection()?;
-    let ids = ids
-        .keys()
-        .filter_map(|key| key.strip_prefix("playlist-item-"))
-        .filter_map(|id| id.parse::<PlaylistItemId>().ok())
-        .collect::<Box<_>>();
-    let mut media_changed = false;
-    for id in &*ids {
-        media_changed |= remove_playlist_item(&mut db_conn, *id)?;
-    }
+    let ids = parse_playlist_item_ids(&ids);
+    let media_changed = immediate_transaction(&mut db_conn, |db_conn| {
+        let mut media_changed = false;
+        for id in &*ids {
+            media_changed |= remove_playlist_item(db_conn, *id)?;
+        }
+        ResourceQueryResult::Ok(media_changed)
+    })?;
 
     app.refresh_playlist(playlist_id).await;
     if media_changed {
@@ -293,61 +434,11 @@ async fn playlist_delete(
     Ok(().into_response())
 }
 
-#[derive(Clone, Debug)]
-struct PlaylistItemRange {
-    first: PlaylistItemId,
-    last: PlaylistItemId,
-}
-
-fn partition_ids_into_ranges(
-    db_conn: &mut SqliteConnection,
-    ids: HashMap<String, String>,
-) -> ResourceQueryResult<Vec<PlaylistItemRange>> {
-    let ids = ids
-        .keys()
+fn parse_playlist_item_ids(ids: &HashMap<String, String>) -> Box<[PlaylistItemId]> {
+    ids.keys()
         .filter_map(|key| key.strip_prefix("playlist-item-"))
         .filter_map(|id| id.parse::<PlaylistItemId>().ok())
-        .collect::<Box<[_]>>();
-    let mut range_dict = HashMap::new();
-    let mut items = Vec::new();
-    for id in &*ids {
-        let item = query_playlist_item(db_conn, *id)?;
-        range_dict.insert(
-            *id,
-            PlaylistItemRange {
-                first: *id,
-                last: *id,
-            },
-        );
-        items.push(item);
-    }
-
-    for item in items {
-        if let Some(prev_item) = item.prev.as_ref() {
-            let prev_range = range_dict.get(prev_item);
-            let cur_range = range_dict.get(&item.id);
-
-            if let Some((prev_range, cur_range)) = prev_range.zip(cur_range) {
-                // merge prev_range and cur_range
-                let merged_range = PlaylistItemRange {
-                    first: prev_range.first,
-                    last: cur_range.last,
-                };
-
-                range_dict.remove(prev_item);
-                range_dict.remove(&item.id);
-                range_dict.insert(merged_range.first, merged_range.clone());
-                range_dict.insert(merged_range.last, merged_range.clone());
-            }
-        }
-    }
-
-    let ranges = range_dict
-        .into_iter()
-        .filter(|(id, range)| *id == range.first)
-        .map(|(_, range)| range)
-        .collect();
-    Ok(ranges)
+        .collect()
 }
 
 async fn playlist_move_up(
@@ -357,29 +448,7 @@ async fn playlist_move_up(
 ) -> ResponseResult<()> {
     let playlist_id = PlaylistId(playlist_id);
     let mut db_conn = app.acquire_db_connection()?;
-    let ranges = partition_ids_into_ranges(&mut db_conn, ids)?;
-    for range in ranges {
-        tracing::info!("{range:?}");
-        let PlaylistItemRange { first, last } = range;
-        let prev = query_playlist_item(&mut db_conn, first)?.prev;
-        let next = query_playlist_item(&mut db_conn, last)?.next;
-        if let Some(next) = next {
-            let next_next = query_playlist_item(&mut db_conn, next)?.next;
-            update_playlist_item_prev_and_next_id(&mut db_conn, next, prev, Some(first))?;
-            update_playlist_item_prev_id(&mut db_conn, first, Some(next))?;
-            update_playlist_item_next_id(&mut db_conn, last, next_next)?;
-            if let Some(next_next) = next_next {
-                update_playlist_item_prev_id(&mut db_conn, next_next, Some(last))?;
-            } else {
-                update_playlist_last_item(&mut db_conn, playlist_id, Some(last))?;
-            }
-            if let Some(prev) = prev {
-                update_playlist_item_next_id(&mut db_conn, prev, Some(next))?;
-            } else {
-                update_playlist_first_item(&mut db_conn, playlist_id, Some(next))?;
-            }
-        }
-    }
+    move_playlist_items_up(&mut db_conn, playlist_id, &parse_playlist_item_ids(&ids))?;
     app.refresh_playlist(playlist_id).await;
     Ok(())
 }
@@ -391,45 +460,105 @@ async fn playlist_move_down(
 ) -> ResponseResult<()> {
     let playlist_id = PlaylistId(playlist_id);
     let mut db_conn = app.acquire_db_connection()?;
-    let ranges = partition_ids_into_ranges(&mut db_conn, ids)?;
-    for range in ranges {
-        let PlaylistItemRange { first, last } = range;
-        let prev = query_playlist_item(&mut db_conn, first)?.prev;
-        let next = query_playlist_item(&mut db_conn, last)?.next;
-        if let Some(prev) = prev {
-            let prev_prev = query_playlist_item(&mut db_conn, prev)?.prev;
-            update_playlist_item_prev_and_next_id(&mut db_conn, prev, Some(last), next)?;
-            update_playlist_item_next_id(&mut db_conn, last, Some(prev))?;
-            update_playlist_item_prev_id(&mut db_conn, first, prev_prev)?;
-            if let Some(prev_prev) = prev_prev {
-                update_playlist_item_next_id(&mut db_conn, prev_prev, Some(first))?;
-            } else {
-                update_playlist_first_item(&mut db_conn, playlist_id, Some(first))?;
-            }
-            if let Some(next) = next {
-                update_playlist_item_prev_id(&mut db_conn, next, Some(prev))?;
-            } else {
-                update_playlist_last_item(&mut db_conn, playlist_id, Some(prev))?;
-            }
+    move_playlist_items_down(&mut db_conn, playlist_id, &parse_playlist_item_ids(&ids))?;
+    app.refresh_playlist(playlist_id).await;
+    Ok(())
+}
+
+/// Move the selected items to the start or the end of the playlist, or after
+/// another item, given by the `target` and `after` fields.
+async fn playlist_move(
+    Path(playlist_id): Path<i32>,
+    State(app): State<Arc<AppState>>,
+    Form(fields): Form<HashMap<String, String>>,
+) -> ResponseResult<()> {
+    let playlist_id = PlaylistId(playlist_id);
+    let target = match fields.get("target").map(String::as_str) {
+        Some("start") => MoveTarget::Start,
+        Some("end") => MoveTarget::End,
+        Some("after") => {
+            MoveTarget::After(fields.get("after").and_then(|id| id.parse().ok()).ok_or(
+                ResponseError::InvalidRequest("no playlist item to move after".into()),
+            )?)
         }
+        _ => return Err(ResponseError::InvalidRequest("invalid move target".into())),
+    };
+    let ids = parse_playlist_item_ids(&fields);
+    if ids.is_empty() {
+        return Err(ResponseError::InvalidRequest("no item selected".into()));
     }
+    let mut db_conn = app.acquire_db_connection()?;
+    move_playlist_items(&mut db_conn, playlist_id, &ids, target)?;
     app.refresh_playlist(playlist_id).await;
     Ok(())
 }
 
+#[derive(Deserialize)]
+#[serde(rename_all = "kebab-case")]
+struct PlaylistShuffleQuery {
+    /// Only shuffle the items after the current one.
+    #[serde(default)]
+    after_current: bool,
+    seed: Option<u64>,
+}
+
+async fn playlist_shuffle(
+    Path(playlist_id): Path<i32>,
+    Query(query): Query<PlaylistShuffleQuery>,
+    State(app): State<Arc<AppState>>,
+) -> ResponseResult<()> {
+    let playlist_id = PlaylistId(playlist_id);
+    let mut rng = match query.seed {
+        Some(seed) => StdRng::seed_from_u64(seed),
+        None => StdRng::from_entropy(),
+    };
+    let mut db_conn = app.acquire_db_connection()?;
+    shuffle_playlist_items(&mut db_conn, playlist_id, query.after_current, &mut rng)?;
+    app.refresh_playlist(playlist_id).await;
+    Ok(())
+}
+
+async fn playlist_fsck(
+    Path(playlist_id): Path<i32>,
+    State(app): State<Arc<AppState>>,
+) -> ResponseResult<Json<IntegrityReport>> {
+    let mut db_conn = app.acquire_db_connection()?;
+    let report = fsck_playlist(&mut db_conn, PlaylistId(playlist_id), false)?;
+    Ok(Json(report))
+}
+
+/// Repairing relinks the items of a broken playlist by insertion order, so
+/// it is not done by GET requests, which may be sent by prefetchers.
+async fn playlist_fsck_repair(
+    Path(playlist_id): Path<i32>,
+    State(app): State<Arc<AppState>>,
+) -> ResponseResult<Json<IntegrityReport>> {
+    let playlist_id = PlaylistId(playlist_id);
+    let mut db_conn = app.acquire_db_connection()?;
+    let report = fsck_playlist(&mut db_conn, playlist_id, true)?;
+    if report.repaired {
+        app.refresh_playlist(playlist_id).await;
+        app.metadata_changed(playlist_id).await;
+    }
+    Ok(Json(report))
+}
+
 async fn update_media(
     Path(media_id): Path<i32>,
     State(app): State<Arc<AppState>>,
 ) -> ResponseResult<impl IntoResponse> {
     let mut db_conn = app.acquire_db_connection()?;
     let media = query_media_with_id(&mut db_conn, MediaId(media_id))?;
-    let resolved_media = resolve_media(
-        &Url::parse(&media.url)
-            .map_err(|e| ResponseError::Generic(anyhow!("unable to parse url of media: {e}")))?,
-        Some(media.media_type.as_str()),
-    )
-    .await
-    .map_err(FetchMediaError::ResolveError)?;
+    let resolved_media = app
+        .resolvers()
+        .resolve_media(
+            &Url::parse(&media.url).map_err(|e| {
+                ResponseError::Generic(anyhow!("unable to parse url of media: {e}"))
+            })?,
+            Some(media.media_type.as_str()),
+        )
+        .await
+        .map_err(FetchMediaError::ResolveError)?;
     let delta_duration_per_media = resolved_media
         .duration
         .map(|d| Duration::seconds_f64(d as f64))
@@ -495,3 +624,122 @@ async fn update_media_metadata(
     }
     Ok(())
 }
+
+async fn media_lists_with_media(
+    Path(media_id): Path<i32>,
+    State(app): State<Arc<AppState>>,
+) -> ResponseResult<Json<Vec<MediaList>>> {
+    let mut db_conn = app.acquire_db_connection()?;
+    let media = query_media_with_id(&mut db_conn, MediaId(media_id))?;
+    Ok(Json(query_media_lists_with_media(&mut db_conn, media.id)?))
+}
+
+async fn media_list_medias(
+    Path(list_id): Path<i32>,
+    State(app): State<Arc<AppState>>,
+) -> ResponseResult<Json<Vec<Media>>> {
+    let mut db_conn = app.acquire_db_connection()?;
+    let media_list = query_media_list_with_id(&mut db_conn, MediaListId(list_id))?;
+    Ok(Json(query_media_list_medias(&mut db_conn, media_list.id)?))
+}
+
+#[derive(Deserialize)]
+struct MediaListUpdateQuery {
+    /// Also apply the changes to playlists the list was imported into.
+    #[serde(default)]
+    sync: bool,
+}
+
+#[derive(Serialize)]
+struct MediaListUpdate {
+    added: Vec<Media>,
+    removed: Vec<Media>,
+    synced_playlists: Vec<PlaylistId>,
+}
+
+async fn update_media_list(
+    Path(list_id): Path<i32>,
+    Query(query): Query<MediaListUpdateQuery>,
+    State(app): State<Arc<AppState>>,
+) -> ResponseResult<Json<MediaListUpdate>> {
+    let mut db_conn = app.acquire_db_connection()?;
+    let media_list = query_media_list_with_id(&mut db_conn, MediaListId(list_id))?;
+    let (new_media_list, entries) = app
+        .resolvers()
+        .resolve_media_list(&Url::parse(&media_list.url).map_err(|e| {
+            ResponseError::Generic(anyhow!("unable to parse url of media list: {e}"))
+        })?)
+        .await
+        .map_err(FetchMediaError::ResolveError)?;
+    let medias = app
+        .resolve_media_list_entries(&mut db_conn, &new_media_list.title, entries, None)
+        .await?;
+
+    let changes = update_media_list_medias(
+        &mut db_conn,
+        media_list.id,
+        NewMediaList {
+            total_duration: total_duration_of(&medias),
+            ..new_media_list
+        },
+        &medias,
+    )?;
+
+    let mut synced_playlists = Vec::new();
+    if query.sync && !(changes.added.is_empty() && changes.removed.is_empty()) {
+        let items = playlist_items_with_media_list_id(&mut db_conn, media_list.id)?;
+        let playlists: HashSet<PlaylistId> = items.iter().map(|item| item.playlist_id).collect();
+        for playlist_id in playlists {
+            let media_changed = sync_media_list_into_playlist(
+                &mut db_conn,
+                playlist_id,
+                media_list.id,
+                &changes.added,
+                &changes.removed,
+            )?;
+            app.refresh_playlist(playlist_id).await;
+            if media_changed {
+                app.media_changed(playlist_id, None).await?;
+            }
+            synced_playlists.push(playlist_id);
+        }
+    }
+
+    Ok(Json(MediaListUpdate {
+        added: changes.added,
+        removed: changes.removed,
+        synced_playlists,
+    }))
+}
+
+#[derive(Deserialize)]
+pub(super) struct SearchQuery {
+    #[serde(default)]
+    pub q: String,
+    #[serde(default)]
+    pub offset: usize,
+    /// Defaults to [`SearchQuery::DEFAULT_LIMIT`].
+    #[serde(default)]
+    pub limit: Option<usize>,
+}
+
+impl SearchQuery {
+    pub const DEFAULT_LIMIT: usize = 50;
+
+    pub fn limit(&self) -> usize {
+        self.limit.unwrap_or(Self::DEFAULT_LIMIT)
+    }
+}
+
+async fn search(
+    Query(query): Query<SearchQuery>,
+    State(app): State<Arc<AppState>>,
+) -> ResponseResult<Json<Vec<Media>>> {
+    let mut db_conn = app.acquire_db_connection()?;
+    Ok(Json(search_medias(
+        &mut db_conn,
+        &query.q,
+        query.offset,
+        query.limit().clamp(1, 500),
+    )?))
+}
@@ -2,13 +2,26 @@ use std::{borrow::Cow, collections::HashSet, sync::Arc};
 
 use super::{
     app::{AppRouter, AppState},
+    history::HistoryQuery,
+    playlist::SearchQuery,
+    stats::StatsQuery,
     ResponseError, ResponseResult,
 };
-use crate::db::{
-    media::{query_media_with_id, Media},
-    playlist::{query_playlist_from_id, query_playlists, Playlist, PlaylistId},
-    playlist_item::{query_playlist_item, PlaylistItem, PlaylistItemId},
-    ResourceQueryResult,
+use crate::{
+    db::{
+        history::{query_play_history, PlayHistoryEntry},
+        media::{
+            query_media_lists_with_media, query_media_types, query_media_with_id, query_medias,
+            search_medias, Media, MediaId, MediaList, MediaSort,
+        },
+        playlist::{query_playlist_from_id, query_playlists, Playlist, PlaylistId, RepeatMode},
+        playlist_item::{
+            playlist_items_with_media_id, query_playlist_item, PlaylistItem, PlaylistItemId,
+        },
+        stats::{query_stats, Stats, StatsPeriod},
+        ResourceQueryResult,
+    },
+    resolvers::ResolverRegistry,
 };
 use axum::{
     extract::{Path, Query, State},
@@ -34,6 +47,11 @@ pub fn ssr_router() -> AppRouter {
         .route("/watch", get(watch_select))
         .route("/playlist/:id/list", get(playlist_get))
         .route("/playlist/:id/controller", get(playlist_controller))
+        .route("/search", get(search))
+        .route("/library", get(library))
+        .route("/media/:id", get(media_details))
+        .route("/history", get(history))
+        .route("/stats", get(stats))
 }
 
 #[derive(TemplateOnce)]
@@ -77,7 +95,6 @@ impl Formatter {
         format!("{:0>2}:{:0>2}:{:0>2}", hours, minutes, seconds)
     }
 
-    #[allow(unused)]
     pub fn datetime(&self, datetime: &PrimitiveDateTime) -> String {
         const ENCODED_FORMAT: EncodedConfig = Config::DEFAULT
             .set_time_precision(TimePrecision::Second {
@@ -222,6 +239,7 @@ struct WatchSelectTemplate<'a> {
     next_offset: Option<usize>,
     prev_offset: Option<usize>,
     formatter: Formatter,
+    resolvers: &'a ResolverRegistry,
 }
 
 #[allow(clippy::type_complexity)]
@@ -254,7 +272,7 @@ async fn watch_select(
     let count = 5;
     let playlists = query_playlists_with_current_items(&mut db_conn, offset, count + 1)?;
     let prev_offset = if offset > 0 {
-        Some(offset.checked_sub(count).unwrap_or_default())
+        Some(offset.saturating_sub(count))
     } else {
         None
     };
@@ -271,6 +289,7 @@ async fn watch_select(
             prev_offset,
             next_offset,
             formatter: Formatter,
+            resolvers: app.resolvers(),
         }
         .render_once()?,
     ))
@@ -309,3 +328,264 @@ async fn playlist_controller(
         .render_once()?,
     ))
 }
+
+#[derive(TemplateOnce)]
+#[template(path = "search.stpl")]
+struct SearchTemplate<'a> {
+    title: &'static str,
+    query: &'a SearchQuery,
+    medias: Vec<Media>,
+    pid: Option<PlaylistId>,
+    /// Query string of the previous and next pages.
+    prev_page: Option<String>,
+    next_page: Option<String>,
+    fmt: Formatter,
+}
+
+async fn search(
+    Query(query): Query<SearchQuery>,
+    State(app): State<Arc<AppState>>,
+) -> ResponseResult<Html<String>> {
+    let mut db_conn = app.acquire_db_connection()?;
+    let count = query.limit().clamp(1, 500);
+    let mut medias = search_medias(&mut db_conn, &query.q, query.offset, count + 1)?;
+    let page = |offset: usize| {
+        let mut page = url::form_urlencoded::Serializer::new(String::new());
+        page.append_pair("q", &query.q);
+        if let Some(limit) = query.limit {
+            page.append_pair("limit", &limit.to_string());
+        }
+        page.append_pair("offset", &offset.to_string()).finish()
+    };
+    let prev_page = (query.offset > 0).then(|| page(query.offset.saturating_sub(count)));
+    let next_page = if medias.len() > count {
+        medias.truncate(count);
+        Some(page(query.offset + count))
+    } else {
+        None
+    };
+    Ok(Html(
+        SearchTemplate {
+            title: "search - plst3",
+            query: &query,
+            medias,
+            pid: app.get_current_playlist().await,
+            prev_page,
+            next_page,
+            fmt: Formatter,
+        }
+        .render_once()?,
+    ))
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "kebab-case")]
+struct LibraryParams {
+    #[serde(default)]
+    sort: MediaSort,
+    #[serde(default)]
+    desc: bool,
+    #[serde(default, rename = "type")]
+    media_type: Option<String>,
+    after: Option<String>,
+    after_id: Option<MediaId>,
+}
+
+#[derive(TemplateOnce)]
+#[template(path = "library.stpl")]
+struct LibraryTemplate<'a> {
+    title: &'static str,
+    medias: Vec<Media>,
+    media_types: Vec<String>,
+    playlists: Vec<Playlist>,
+    current_id: Option<PlaylistId>,
+    sort: MediaSort,
+    desc: bool,
+    media_type: Option<&'a str>,
+    /// Query string of the next page, if there is one.
+    next_page: Option<String>,
+    fmt: Formatter,
+}
+
+async fn library(
+    Query(params): Query<LibraryParams>,
+    State(app): State<Arc<AppState>>,
+) -> ResponseResult<Html<String>> {
+    let mut db_conn = app.acquire_db_connection()?;
+    let count = 50;
+    let media_type = params.media_type.as_deref().filter(|t| !t.is_empty());
+    let after = params
+        .after
+        .as_deref()
+        .and_then(|key| params.sort.parse_key(key))
+        .zip(params.after_id);
+    let mut medias = query_medias(
+        &mut db_conn,
+        params.sort,
+        params.desc,
+        media_type,
+        after,
+        count + 1,
+    )?;
+    let next_page = if medias.len() > count {
+        medias.truncate(count);
+        medias.last().map(|last| {
+            let mut query = url::form_urlencoded::Serializer::new(String::new());
+            query
+                .append_pair("sort", params.sort.as_str())
+                .append_pair("desc", &params.desc.to_string())
+                .append_pair("after", &params.sort.key_of(last).to_string())
+                .append_pair("after-id", &last.id.to_string());
+            if let Some(media_type) = media_type {
+                query.append_pair("type", media_type);
+            }
+            query.finish()
+        })
+    } else {
+        None
+    };
+    Ok(Html(
+        LibraryTemplate {
+            title: "library - plst3",
+            medias,
+            media_types: query_media_types(&mut db_conn)?,
+            playlists: query_playlists(&mut db_conn, 0, 100)?,
+            current_id: app.get_current_playlist().await,
+            sort: params.sort,
+            desc: params.desc,
+            media_type,
+            next_page,
+            fmt: Formatter,
+        }
+        .render_once()?,
+    ))
+}
+
+#[derive(TemplateOnce)]
+#[template(path = "media.stpl")]
+struct MediaTemplate {
+    title: String,
+    media: Media,
+    /// Playlists containing the media, with the number of occurrences.
+    playlists: Vec<(Playlist, usize)>,
+    media_lists: Vec<MediaList>,
+    fmt: Formatter,
+}
+
+async fn media_details(
+    Path(media_id): Path<i32>,
+    State(app): State<Arc<AppState>>,
+) -> ResponseResult<Html<String>> {
+    let mut db_conn = app.acquire_db_connection()?;
+    let media = query_media_with_id(&mut db_conn, MediaId(media_id))?;
+    let mut occurrences = Vec::<(PlaylistId, usize)>::new();
+    for item in playlist_items_with_media_id(&mut db_conn, media.id)?.iter() {
+        match occurrences
+            .iter_mut()
+            .find(|(id, _)| *id == item.playlist_id)
+        {
+            Some((_, count)) => *count += 1,
+            None => occurrences.push((item.playlist_id, 1)),
+        }
+    }
+    let playlists = occurrences
+        .into_iter()
+        .map(|(playlist_id, count)| Ok((query_playlist_from_id(&mut db_conn, playlist_id)?, count)))
+        .collect::<ResourceQueryResult<Vec<_>>>()?;
+    Ok(Html(
+        MediaTemplate {
+            title: format!("{} - plst3", media.display_string()),
+            media_lists: query_media_lists_with_media(&mut db_conn, media.id)?,
+            media,
+            playlists,
+            fmt: Formatter,
+        }
+        .render_once()?,
+    ))
+}
+
+#[derive(TemplateOnce)]
+#[template(path = "history.stpl")]
+struct HistoryTemplate<'a> {
+    title: &'static str,
+    history: Vec<(PlayHistoryEntry, Media)>,
+    playlists: Vec<Playlist>,
+    query: &'a HistoryQuery,
+    playlist_id: Option<PlaylistId>,
+    /// Query string of the previous and next pages.
+    prev_page: Option<String>,
+    next_page: Option<String>,
+    fmt: Formatter,
+}
+
+async fn history(
+    Query(query): Query<HistoryQuery>,
+    State(app): State<Arc<AppState>>,
+) -> ResponseResult<Html<String>> {
+    let filter = query.filter()?;
+    let mut db_conn = app.acquire_db_connection()?;
+    let count = query.limit().clamp(1, 500);
+    let offset = query.offset.max(0);
+    let mut history = query_play_history(&mut db_conn, &filter, offset, count + 1)?;
+    let page = |offset: i64| {
+        let mut page = url::form_urlencoded::Serializer::new(String::new());
+        for (key, value) in [
+            ("playlist", &query.playlist),
+            ("from", &query.from),
+            ("to", &query.to),
+        ] {
+            if let Some(value) = value {
+                page.append_pair(key, value);
+            }
+        }
+        if let Some(limit) = query.limit {
+            page.append_pair("limit", &limit.to_string());
+        }
+        page.append_pair("offset", &offset.to_string()).finish()
+    };
+    let prev_page = (offset > 0).then(|| page((offset - count).max(0)));
+    let next_page = if history.len() as i64 > count {
+        history.truncate(count as usize);
+        Some(page(offset + count))
+    } else {
+        None
+    };
+    Ok(Html(
+        HistoryTemplate {
+            title: "history - plst3",
+            history,
+            playlists: query_playlists(&mut db_conn, 0, 100)?,
+            query: &query,
+            playlist_id: filter.playlist_id,
+            prev_page,
+            next_page,
+            fmt: Formatter,
+        }
+        .render_once()?,
+    ))
+}
+
+#[derive(TemplateOnce)]
+#[template(path = "stats.stpl")]
+struct StatsTemplate {
+    title: &'static str,
+    stats: Stats,
+    period: StatsPeriod,
+    fmt: Formatter,
+}
+
+async fn stats(
+    Query(query): Query<StatsQuery>,
+    State(app): State<Arc<AppState>>,
+) -> ResponseResult<Html<String>> {
+    let mut db_conn = app.acquire_db_connection()?;
+    Ok(Html(
+        StatsTemplate {
+            title: "stats - plst3",
+            stats: query_stats(&mut db_conn, query.period, query.limit.clamp(1, 500))?,
+            period: query.period,
+            fmt: Formatter,
+        }
+        .render_once()?,
+    ))
+}
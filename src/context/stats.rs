@@ -0,0 +1,41 @@
+use std::sync::Arc;
+
+use super::{
+    app::{AppRouter, AppState},
+    ResponseResult,
+};
+use crate::db::stats::{query_stats, Stats, StatsPeriod};
+use axum::{
+    extract::{Query, State},
+    routing::get,
+    Json,
+};
+use serde::Deserialize;
+
+pub fn stats_router() -> AppRouter {
+    AppRouter::new().route("/api/stats", get(stats))
+}
+
+#[derive(Deserialize)]
+pub(super) struct StatsQuery {
+    #[serde(default)]
+    pub period: StatsPeriod,
+    #[serde(default = "default_stats_limit")]
+    pub limit: i64,
+}
+
+fn default_stats_limit() -> i64 {
+    20
+}
+
+async fn stats(
+    Query(query): Query<StatsQuery>,
+    State(app): State<Arc<AppState>>,
+) -> ResponseResult<Json<Stats>> {
+    let mut db_conn = app.acquire_db_connection()?;
+    Ok(Json(query_stats(
+        &mut db_conn,
+        query.period,
+        query.limit.clamp(1, 500),
+    )?))
+}
@@ -0,0 +1,174 @@
+use diesel::{prelude::*, SqliteConnection};
+use serde::Serialize;
+use time::{Date, Duration, PrimitiveDateTime, Time};
+
+use crate::schema::{medias, play_history};
+
+use super::{
+    immediate_transaction,
+    media::{Media, MediaId},
+    playlist::PlaylistId,
+    playlist_item::PlaylistItemId,
+};
+
+/// A media that was played, and for how long.
+#[derive(Queryable, Selectable, Serialize, Debug)]
+#[diesel(table_name = play_history)]
+#[diesel(check_for_backend(diesel::sqlite::Sqlite))]
+pub struct PlayHistoryEntry {
+    pub id: i32,
+    pub media_id: MediaId,
+    pub playlist_id: Option<PlaylistId>,
+    pub playlist_item_id: Option<PlaylistItemId>,
+    pub started_at: PrimitiveDateTime,
+    pub ended_at: Option<PrimitiveDateTime>,
+    /// Whether the media was played until the end, rather than skipped.
+    pub completed: bool,
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = play_history)]
+struct NewPlayHistoryEntry {
+    media_id: MediaId,
+    playlist_id: PlaylistId,
+    playlist_item_id: PlaylistItemId,
+}
+
+/// Restrictions on the history entries to query. Dates are in UTC and both
+/// ends are inclusive.
+#[derive(Default, Debug)]
+pub struct HistoryFilter {
+    pub playlist_id: Option<PlaylistId>,
+    pub from: Option<Date>,
+    pub to: Option<Date>,
+}
+
+/// End the entry of whatever `playlist_id` was playing, if any.
+pub fn end_play(
+    db_conn: &mut SqliteConnection,
+    pid: PlaylistId,
+    is_completed: bool,
+) -> Result<usize, diesel::result::Error> {
+    use crate::schema::play_history::dsl::*;
+    diesel::update(play_history)
+        .filter(playlist_id.eq(pid))
+        .filter(ended_at.is_null())
+        .set((
+            ended_at.eq(diesel::dsl::now.nullable()),
+            completed.eq(is_completed),
+        ))
+        .execute(db_conn)
+}
+
+/// Record that `playlist_id` started playing an item, ending the previous
+/// entry of the playlist.
+pub fn start_play(
+    db_conn: &mut SqliteConnection,
+    playlist_id: PlaylistId,
+    playlist_item_id: PlaylistItemId,
+    media_id: MediaId,
+) -> Result<PlayHistoryEntry, diesel::result::Error> {
+    immediate_transaction(db_conn, |db_conn| {
+        end_play(db_conn, playlist_id, false)?;
+        diesel::insert_into(play_history::table)
+            .values(NewPlayHistoryEntry {
+                media_id,
+                playlist_id,
+                playlist_item_id,
+            })
+            .returning(PlayHistoryEntry::as_returning())
+            .get_result(db_conn)
+    })
+}
+
+/// Most recent plays first.
+pub fn query_play_history(
+    db_conn: &mut SqliteConnection,
+    filter: &HistoryFilter,
+    offset: i64,
+    limit: i64,
+) -> Result<Vec<(PlayHistoryEntry, Media)>, diesel::result::Error> {
+    let mut query = play_history::table
+        .inner_join(medias::table)
+        .select((PlayHistoryEntry::as_select(), Media::as_select()))
+        .into_boxed();
+    if let Some(playlist_id) = filter.playlist_id {
+        query = query.filter(play_history::playlist_id.eq(playlist_id));
+    }
+    if let Some(from) = filter.from {
+        query =
+            query.filter(play_history::started_at.ge(PrimitiveDateTime::new(from, Time::MIDNIGHT)));
+    }
+    if let Some(to) = filter.to {
+        let end = PrimitiveDateTime::new(to, Time::MIDNIGHT) + Duration::DAY;
+        query = query.filter(play_history::started_at.lt(end));
+    }
+    query
+        .order((play_history::started_at.desc(), play_history::id.desc()))
+        .offset(offset)
+        .limit(limit)
+        .load(db_conn)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::{
+        playlist::{append_to_playlist, create_empty_playlist},
+        test_utils::{insert_test_media, TestDb},
+    };
+
+    #[tokio::test]
+    async fn starting_a_play_ends_the_previous_one() {
+        let db = TestDb::new();
+        let mut db_conn = db.pool.get().unwrap();
+        let media = insert_test_media(&mut db_conn, 10);
+        let playlist_id = create_empty_playlist(&mut db_conn, "test").await.unwrap();
+        let item_ids = append_to_playlist(
+            &mut db_conn,
+            playlist_id,
+            None,
+            &[media.id; 2],
+            None,
+            Duration::seconds(20),
+        )
+        .unwrap();
+
+        start_play(&mut db_conn, playlist_id, item_ids[0], media.id).unwrap();
+        end_play(&mut db_conn, playlist_id, true).unwrap();
+        start_play(&mut db_conn, playlist_id, item_ids[1], media.id).unwrap();
+        start_play(&mut db_conn, playlist_id, item_ids[0], media.id).unwrap();
+
+        let filter = HistoryFilter {
+            playlist_id: Some(playlist_id),
+            ..Default::default()
+        };
+        let history = query_play_history(&mut db_conn, &filter, 0, 10).unwrap();
+        let history = history
+            .iter()
+            .map(|(entry, _)| {
+                (
+                    entry.playlist_item_id,
+                    entry.ended_at.is_some(),
+                    entry.completed,
+                )
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            history,
+            [
+                (Some(item_ids[0]), false, false),
+                (Some(item_ids[1]), true, false),
+                (Some(item_ids[0]), true, true),
+            ]
+        );
+
+        let filter = HistoryFilter {
+            to: Some(Date::from_calendar_date(2000, time::Month::January, 1).unwrap()),
+            ..Default::default()
+        };
+        assert!(query_play_history(&mut db_conn, &filter, 0, 10)
+            .unwrap()
+            .is_empty());
+    }
+}
@@ -0,0 +1,329 @@
+use super::{
+    immediate_transaction,
+    media::{DurationWrapper, MediaId},
+    playlist::{query_playlist_from_id, PlaylistId},
+    playlist_item::{PlaylistItem, PlaylistItemId},
+    ResourceQueryResult,
+};
+use diesel::{prelude::*, SqliteConnection};
+use serde::Serialize;
+use std::{
+    collections::{HashMap, HashSet},
+    fmt::Display,
+};
+
+/// An inconsistency between a playlist and its items.
+#[derive(Debug, PartialEq, Eq, Serialize)]
+#[serde(tag = "kind", rename_all = "kebab-case")]
+pub enum IntegrityIssue {
+    /// `next` of `item` (or the first item of the playlist, if `item` is
+    /// `None`) references a missing item or an item of another playlist.
+    BrokenLink {
+        item: Option<PlaylistItemId>,
+        next: PlaylistItemId,
+    },
+    /// `prev` of `item` does not reference the item linking to it.
+    PrevMismatch {
+        item: PlaylistItemId,
+        prev: Option<PlaylistItemId>,
+        expected: Option<PlaylistItemId>,
+    },
+    /// Following `next` from `item` leads back to an already visited item.
+    Cycle {
+        item: PlaylistItemId,
+    },
+    /// An item of the playlist that can not be reached from its first item.
+    Orphan {
+        item: PlaylistItemId,
+    },
+    LastItemMismatch {
+        stored: Option<PlaylistItemId>,
+        expected: Option<PlaylistItemId>,
+    },
+    /// The current item is not an item of the playlist.
+    DanglingCurrentItem {
+        item: PlaylistItemId,
+    },
+    NumItemsDrift {
+        stored: i32,
+        actual: i32,
+    },
+    TotalDurationDrift {
+        stored: i64,
+        actual: i64,
+    },
+}
+
+impl Display for IntegrityIssue {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        fn fmt_id(id: &Option<PlaylistItemId>) -> String {
+            id.map(|id| id.to_string())
+                .unwrap_or_else(|| "none".to_string())
+        }
+
+        match self {
+            Self::BrokenLink { item: None, next } => {
+                write!(f, "first item {next} does not exist in the playlist")
+            }
+            Self::BrokenLink {
+                item: Some(item),
+                next,
+            } => write!(
+                f,
+                "item {item} links to {next}, which is not in the playlist"
+            ),
+            Self::PrevMismatch {
+                item,
+                prev,
+                expected,
+            } => write!(
+                f,
+                "item {item} has prev {}, expected {}",
+                fmt_id(prev),
+                fmt_id(expected)
+            ),
+            Self::Cycle { item } => write!(f, "item {item} links back into the playlist"),
+            Self::Orphan { item } => write!(f, "item {item} is unreachable"),
+            Self::LastItemMismatch { stored, expected } => write!(
+                f,
+                "last item is {}, expected {}",
+                fmt_id(stored),
+                fmt_id(expected)
+            ),
+            Self::DanglingCurrentItem { item } => {
+                write!(f, "current item {item} is not in the playlist")
+            }
+            Self::NumItemsDrift { stored, actual } => {
+                write!(f, "item count is {stored}, actual {actual}")
+            }
+            Self::TotalDurationDrift { stored, actual } => {
+                write!(f, "total duration is {stored}s, actual {actual}s")
+            }
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct IntegrityReport {
+    pub playlist_id: PlaylistId,
+    /// Issues found before repairing.
+    pub issues: Vec<IntegrityIssue>,
+    pub repaired: bool,
+}
+
+fn load_items(
+    db_conn: &mut SqliteConnection,
+    pid: PlaylistId,
+) -> ResourceQueryResult<Vec<PlaylistItem>> {
+    use crate::schema::playlist_items::dsl::*;
+    Ok(playlist_items
+        .filter(playlist_id.eq(pid))
+        .order((add_timestamp.asc(), id.asc()))
+        .select(PlaylistItem::as_select())
+        .load(db_conn)?)
+}
+
+/// Sum of the durations of the medias of `items`, in seconds.
+fn total_duration_of_items(
+    db_conn: &mut SqliteConnection,
+    items: &[PlaylistItem],
+) -> ResourceQueryResult<i64> {
+    use crate::schema::medias::dsl::*;
+    let media_ids = items.iter().map(|item| item.media_id).collect::<Vec<_>>();
+    let durations: HashMap<MediaId, Option<DurationWrapper>> = medias
+        .filter(id.eq_any(&media_ids))
+        .select((id, duration))
+        .load(db_conn)?
+        .into_iter()
+        .collect();
+    Ok(media_ids
+        .iter()
+        .filter_map(|media_id| durations.get(media_id).copied().flatten())
+        .map(|d| d.whole_seconds())
+        .sum())
+}
+
+/// Check that the linked list and the counters of a playlist agree.
+pub fn check_playlist(
+    db_conn: &mut SqliteConnection,
+    playlist_id: PlaylistId,
+) -> ResourceQueryResult<Vec<IntegrityIssue>> {
+    let playlist = query_playlist_from_id(db_conn, playlist_id)?;
+    let items = load_items(db_conn, playlist_id)?;
+    let by_id: HashMap<PlaylistItemId, &PlaylistItem> =
+        items.iter().map(|item| (item.id, item)).collect();
+
+    let mut issues = Vec::new();
+    let mut visited = HashSet::new();
+    let mut prev: Option<&PlaylistItem> = None;
+    let mut next_id = playlist.first_playlist_item;
+    while let Some(item_id) = next_id {
+        let Some(item) = by_id.get(&item_id).copied() else {
+            issues.push(IntegrityIssue::BrokenLink {
+                item: prev.map(|prev| prev.id),
+                next: item_id,
+            });
+            break;
+        };
+        if !visited.insert(item_id) {
+            issues.push(IntegrityIssue::Cycle {
+                item: prev.map(|prev| prev.id).unwrap_or(item_id),
+            });
+            break;
+        }
+        let expected = prev.map(|prev| prev.id);
+        if item.prev != expected {
+            issues.push(IntegrityIssue::PrevMismatch {
+                item: item_id,
+                prev: item.prev,
+                expected,
+            });
+        }
+        prev = Some(item);
+        next_id = item.next;
+    }
+
+    let expected_last = prev.map(|prev| prev.id);
+    if playlist.last_playlist_item != expected_last {
+        issues.push(IntegrityIssue::LastItemMismatch {
+            stored: playlist.last_playlist_item,
+            expected: expected_last,
+        });
+    }
+    for item in items.iter().filter(|item| !visited.contains(&item.id)) {
+        issues.push(IntegrityIssue::Orphan { item: item.id });
+    }
+    if let Some(current) = playlist.current_item {
+        if !by_id.contains_key(&current) {
+            issues.push(IntegrityIssue::DanglingCurrentItem { item: current });
+        }
+    }
+
+    let actual = items.len() as i32;
+    if playlist.num_items != actual {
+        issues.push(IntegrityIssue::NumItemsDrift {
+            stored: playlist.num_items,
+            actual,
+        });
+    }
+    let actual = total_duration_of_items(db_conn, &items)?;
+    let stored = playlist.total_duration.whole_seconds();
+    if stored != actual {
+        issues.push(IntegrityIssue::TotalDurationDrift { stored, actual });
+    }
+    Ok(issues)
+}
+
+/// Relink every item of a playlist in `add_timestamp` order and recompute
+/// its counters.
+pub fn repair_playlist(
+    db_conn: &mut SqliteConnection,
+    playlist_id: PlaylistId,
+) -> ResourceQueryResult<()> {
+    immediate_transaction(db_conn, |db_conn| {
+        let playlist = query_playlist_from_id(db_conn, playlist_id)?;
+        let items = load_items(db_conn, playlist_id)?;
+        for (index, item) in items.iter().enumerate() {
+            use crate::schema::playlist_items::dsl::*;
+            let prev_id = index.checked_sub(1).map(|index| items[index].id);
+            let next_id = items.get(index + 1).map(|item| item.id);
+            diesel::update(playlist_items)
+                .filter(id.eq(item.id))
+                .set((prev.eq(prev_id), next.eq(next_id)))
+                .execute(db_conn)?;
+        }
+
+        let item_duration = total_duration_of_items(db_conn, &items)?;
+        let current = playlist
+            .current_item
+            .filter(|current| items.iter().any(|item| item.id == *current));
+        {
+            use crate::schema::playlists::dsl::*;
+            diesel::update(playlists)
+                .filter(id.eq(playlist_id))
+                .set((
+                    first_playlist_item.eq(items.first().map(|item| item.id)),
+                    last_playlist_item.eq(items.last().map(|item| item.id)),
+                    current_item.eq(current),
+                    num_items.eq(items.len() as i32),
+                    total_duration.eq(i32::try_from(item_duration).unwrap_or(i32::MAX)),
+                ))
+                .execute(db_conn)?;
+        }
+        Ok(())
+    })
+}
+
+/// Check a playlist, repairing it if asked to and if anything is wrong.
+pub fn fsck_playlist(
+    db_conn: &mut SqliteConnection,
+    playlist_id: PlaylistId,
+    repair: bool,
+) -> ResourceQueryResult<IntegrityReport> {
+    let issues = check_playlist(db_conn, playlist_id)?;
+    let repaired = repair && !issues.is_empty();
+    if repaired {
+        repair_playlist(db_conn, playlist_id)?;
+    }
+    Ok(IntegrityReport {
+        playlist_id,
+        issues,
+        repaired,
+    })
+}
+
+/// [`fsck_playlist`] every playlist.
+pub fn fsck_all_playlists(
+    db_conn: &mut SqliteConnection,
+    repair: bool,
+) -> ResourceQueryResult<Vec<IntegrityReport>> {
+    use crate::schema::playlists::dsl::*;
+    let ids: Vec<PlaylistId> = playlists.select(id).order(id.asc()).load(db_conn)?;
+    ids.into_iter()
+        .map(|playlist_id| fsck_playlist(db_conn, playlist_id, repair))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::{
+        playlist::{append_to_playlist, create_empty_playlist},
+        playlist_item::{update_playlist_item_next_id, update_playlist_item_prev_id},
+        test_utils::{insert_test_media, TestDb},
+    };
+    use time::Duration;
+
+    #[tokio::test]
+    async fn repair_fixes_broken_playlist() {
+        let db = TestDb::new();
+        let mut db_conn = db.pool.get().unwrap();
+        let media = insert_test_media(&mut db_conn, 10);
+        let playlist_id = create_empty_playlist(&mut db_conn, "test").await.unwrap();
+        let item_ids = append_to_playlist(
+            &mut db_conn,
+            playlist_id,
+            None,
+            &[media.id; 4],
+            None,
+            Duration::seconds(40),
+        )
+        .unwrap();
+        assert_eq!(check_playlist(&mut db_conn, playlist_id).unwrap(), vec![]);
+
+        // skip the third item, and make the last one point back to the first
+        update_playlist_item_next_id(&mut db_conn, item_ids[1], Some(item_ids[3])).unwrap();
+        update_playlist_item_next_id(&mut db_conn, item_ids[3], Some(item_ids[0])).unwrap();
+        update_playlist_item_prev_id(&mut db_conn, item_ids[3], Some(item_ids[1])).unwrap();
+        let issues = check_playlist(&mut db_conn, playlist_id).unwrap();
+        assert!(issues.contains(&IntegrityIssue::Cycle { item: item_ids[3] }));
+        assert!(issues.contains(&IntegrityIssue::Orphan { item: item_ids[2] }));
+
+        let report = fsck_playlist(&mut db_conn, playlist_id, true).unwrap();
+        assert!(report.repaired);
+        assert_eq!(check_playlist(&mut db_conn, playlist_id).unwrap(), vec![]);
+        let playlist = query_playlist_from_id(&mut db_conn, playlist_id).unwrap();
+        assert_eq!(playlist.first_playlist_item, Some(item_ids[0]));
+        assert_eq!(playlist.last_playlist_item, Some(item_ids[3]));
+    }
+}
@@ -0,0 +1,302 @@
+use std::{fmt::Display, str::FromStr};
+
+use anyhow::{anyhow, Result};
+use diesel::{
+    deserialize::{FromSql, FromSqlRow},
+    expression::AsExpression,
+    prelude::*,
+    serialize::ToSql,
+    sql_types::{Integer, Text},
+    sqlite::Sqlite,
+    ExpressionMethods, Queryable, Selectable, SelectableHelper, SqliteConnection,
+};
+use serde::{Deserialize, Serialize};
+use time::PrimitiveDateTime;
+
+use crate::{
+    db::{ResourceQueryError, ResourceType},
+    schema::jobs,
+};
+
+use super::{
+    playlist::{AddPosition, PlaylistId},
+    ResourceQueryResult,
+};
+
+#[derive(
+    Clone, Copy, PartialEq, Eq, Debug, Hash, FromSqlRow, AsExpression, Serialize, Deserialize,
+)]
+#[diesel(sql_type = Integer)]
+#[serde(transparent)]
+pub struct JobId(pub i32);
+
+impl FromSql<Integer, Sqlite> for JobId {
+    fn from_sql(
+        bytes: <Sqlite as diesel::backend::Backend>::RawValue<'_>,
+    ) -> diesel::deserialize::Result<Self> {
+        Ok(Self(<i32 as FromSql<Integer, Sqlite>>::from_sql(bytes)?))
+    }
+}
+
+impl ToSql<Integer, Sqlite> for JobId {
+    fn to_sql<'b>(
+        &'b self,
+        out: &mut diesel::serialize::Output<'b, '_, Sqlite>,
+    ) -> diesel::serialize::Result {
+        <i32 as ToSql<Integer, Sqlite>>::to_sql(&self.0, out)
+    }
+}
+
+impl Display for JobId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, FromSqlRow, AsExpression, Serialize, Deserialize)]
+#[diesel(sql_type = Text)]
+#[serde(rename_all = "kebab-case")]
+pub enum JobStatus {
+    Pending,
+    Running,
+    Done,
+    Failed,
+    Cancelled,
+}
+
+impl JobStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Pending => "pending",
+            Self::Running => "running",
+            Self::Done => "done",
+            Self::Failed => "failed",
+            Self::Cancelled => "cancelled",
+        }
+    }
+}
+
+impl FromStr for JobStatus {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "pending" => Ok(Self::Pending),
+            "running" => Ok(Self::Running),
+            "done" => Ok(Self::Done),
+            "failed" => Ok(Self::Failed),
+            "cancelled" => Ok(Self::Cancelled),
+            s => Err(anyhow!("invalid job status: {s}")),
+        }
+    }
+}
+
+impl FromSql<Text, Sqlite> for JobStatus {
+    fn from_sql(
+        bytes: <Sqlite as diesel::backend::Backend>::RawValue<'_>,
+    ) -> diesel::deserialize::Result<Self> {
+        Ok(<String as FromSql<Text, Sqlite>>::from_sql(bytes)?.parse()?)
+    }
+}
+
+impl ToSql<Text, Sqlite> for JobStatus {
+    fn to_sql<'b>(
+        &'b self,
+        out: &mut diesel::serialize::Output<'b, '_, Sqlite>,
+    ) -> diesel::serialize::Result {
+        <str as ToSql<Text, Sqlite>>::to_sql(self.as_str(), out)
+    }
+}
+
+/// A request to resolve `url` and add the result to a playlist, processed in
+/// the background.
+#[derive(Queryable, Selectable, Serialize, Debug)]
+#[diesel(table_name = jobs)]
+#[diesel(check_for_backend(diesel::sqlite::Sqlite))]
+pub struct Job {
+    pub id: JobId,
+    pub playlist_id: PlaylistId,
+    pub url: String,
+    pub position: AddPosition,
+    pub prefer_list: bool,
+    pub status: JobStatus,
+    pub error: Option<String>,
+    pub add_timestamp: PrimitiveDateTime,
+    pub update_timestamp: PrimitiveDateTime,
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = jobs)]
+pub struct NewJob<'a> {
+    pub playlist_id: PlaylistId,
+    pub url: &'a str,
+    pub position: AddPosition,
+    pub prefer_list: bool,
+}
+
+pub fn insert_job(
+    db_conn: &mut SqliteConnection,
+    job: NewJob,
+) -> Result<Job, diesel::result::Error> {
+    diesel::insert_into(jobs::table)
+        .values(job)
+        .returning(Job::as_returning())
+        .get_result(db_conn)
+}
+
+pub fn query_job(db_conn: &mut SqliteConnection, job_id: JobId) -> ResourceQueryResult<Job> {
+    use crate::schema::jobs::dsl::*;
+    jobs.filter(id.eq(job_id))
+        .select(Job::as_select())
+        .first(db_conn)
+        .optional()?
+        .ok_or(ResourceQueryError::ResourceNotFound(
+            ResourceType::Job,
+            job_id.into(),
+        ))
+}
+
+/// Most recent jobs first.
+pub fn query_jobs(
+    db_conn: &mut SqliteConnection,
+    pid: Option<PlaylistId>,
+    limit: i64,
+) -> Result<Vec<Job>, diesel::result::Error> {
+    use crate::schema::jobs::dsl::*;
+    let mut query = jobs.into_boxed();
+    if let Some(pid) = pid {
+        query = query.filter(playlist_id.eq(pid));
+    }
+    query
+        .order(id.desc())
+        .limit(limit)
+        .select(Job::as_select())
+        .load(db_conn)
+}
+
+/// Mark the oldest pending job as running and return it.
+pub fn claim_pending_job(
+    db_conn: &mut SqliteConnection,
+) -> Result<Option<Job>, diesel::result::Error> {
+    use crate::schema::jobs::dsl::*;
+    db_conn.immediate_transaction(|db_conn| {
+        let Some(job_id) = jobs
+            .filter(status.eq(JobStatus::Pending))
+            .order(id.asc())
+            .select(id)
+            .first::<JobId>(db_conn)
+            .optional()?
+        else {
+            return Ok(None);
+        };
+        diesel::update(jobs.filter(id.eq(job_id)))
+            .set((
+                status.eq(JobStatus::Running),
+                update_timestamp.eq(diesel::dsl::now),
+            ))
+            .returning(Job::as_returning())
+            .get_result(db_conn)
+            .optional()
+    })
+}
+
+/// Change the status of a job, but only if its current status is one of
+/// `from`. Returns `None` if the job was not in one of these states.
+pub fn transition_job(
+    db_conn: &mut SqliteConnection,
+    job_id: JobId,
+    from: &[JobStatus],
+    to: JobStatus,
+    new_error: Option<&str>,
+) -> Result<Option<Job>, diesel::result::Error> {
+    use crate::schema::jobs::dsl::*;
+    diesel::update(jobs.filter(id.eq(job_id)).filter(status.eq_any(from)))
+        .set((
+            status.eq(to),
+            error.eq(new_error),
+            update_timestamp.eq(diesel::dsl::now),
+        ))
+        .returning(Job::as_returning())
+        .get_result(db_conn)
+        .optional()
+}
+
+/// Jobs interrupted by a restart are run again.
+pub fn reset_running_jobs(db_conn: &mut SqliteConnection) -> Result<usize, diesel::result::Error> {
+    use crate::schema::jobs::dsl::*;
+    diesel::update(jobs.filter(status.eq(JobStatus::Running)))
+        .set(status.eq(JobStatus::Pending))
+        .execute(db_conn)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::{playlist::create_empty_playlist, test_utils::TestDb};
+
+    #[tokio::test]
+    async fn jobs_follow_their_lifecycle() {
+        use JobStatus::*;
+        let db = TestDb::new();
+        let mut db_conn = db.pool.get().unwrap();
+        let playlist_id = create_empty_playlist(&mut db_conn, "jobs").await.unwrap();
+        let [first, second] = ["https://a.test/1", "https://a.test/2"].map(|url| {
+            let job = NewJob {
+                playlist_id,
+                url,
+                position: AddPosition::AddToEnd,
+                prefer_list: false,
+            };
+            insert_job(&mut db_conn, job).unwrap().id
+        });
+
+        // pending jobs are claimed oldest first
+        let claimed = claim_pending_job(&mut db_conn).unwrap().unwrap();
+        assert_eq!((claimed.id, claimed.status), (first, Running));
+        let claimed = claim_pending_job(&mut db_conn).unwrap().unwrap();
+        assert_eq!((claimed.id, claimed.status), (second, Running));
+        assert!(claim_pending_job(&mut db_conn).unwrap().is_none());
+
+        let mut transition = |job_id, from: &[JobStatus], to, error| {
+            transition_job(&mut db_conn, job_id, from, to, error)
+                .unwrap()
+                .map(|job| (job.status, job.error))
+        };
+        let retry = [Failed, Cancelled];
+        let cancel = [Pending, Running];
+
+        // only failed or cancelled jobs are retried, and done jobs stay done
+        assert_eq!(transition(first, &retry, Pending, None), None);
+        assert_eq!(
+            transition(first, &[Running], Done, None),
+            Some((Done, None))
+        );
+        assert_eq!(transition(first, &retry, Pending, None), None);
+        assert_eq!(transition(first, &cancel, Cancelled, None), None);
+
+        assert_eq!(
+            transition(second, &[Running], Failed, Some("unreachable")),
+            Some((Failed, Some("unreachable".to_owned())))
+        );
+        assert_eq!(transition(second, &cancel, Cancelled, None), None);
+        assert_eq!(
+            transition(second, &retry, Pending, None),
+            Some((Pending, None))
+        );
+        assert_eq!(
+            transition(second, &cancel, Cancelled, None),
+            Some((Cancelled, None))
+        );
+        assert_eq!(
+            transition(second, &retry, Pending, None),
+            Some((Pending, None))
+        );
+
+        // interrupted jobs are queued again
+        let claimed = claim_pending_job(&mut db_conn).unwrap().unwrap();
+        assert_eq!(claimed.id, second);
+        assert_eq!(reset_running_jobs(&mut db_conn).unwrap(), 1);
+        assert_eq!(query_job(&mut db_conn, second).unwrap().status, Pending);
+        assert_eq!(query_job(&mut db_conn, first).unwrap().status, Done);
+    }
+}
@@ -1,6 +1,6 @@
 use crate::{
     db::{ResourceQueryError, ResourceType},
-    schema::{media_lists, medias},
+    schema::{media_list_entries, media_lists, medias},
 };
 use anyhow::Result;
 use diesel::{
@@ -8,12 +8,12 @@ use diesel::{
     expression::AsExpression,
     prelude::*,
     serialize::{IsNull, ToSql},
-    sql_types::{Integer, Text},
+    sql_types::{BigInt, Bool, Integer, Text},
     sqlite::Sqlite,
 };
 use sailfish::runtime::Render;
 use serde::{Deserialize, Serialize};
-use std::{borrow::Cow, fmt::Display, ops::Deref, str::FromStr};
+use std::{borrow::Cow, collections::HashSet, fmt::Display, ops::Deref, str::FromStr};
 use time::{Duration, PrimitiveDateTime};
 use url::Url;
 
@@ -141,45 +141,32 @@ impl Deref for DurationWrapper {
     }
 }
 
-#[derive(PartialEq, Eq, Debug, Clone, FromSqlRow)]
-pub struct MediaIdList(pub Vec<MediaId>);
-
-impl AsExpression<Text> for MediaIdList {
-    type Expression = <String as AsExpression<Text>>::Expression;
-
-    fn as_expression(self) -> Self::Expression {
-        let text = self
-            .0
-            .iter()
-            .map(|id| id.0.to_string())
-            .collect::<Vec<_>>()
-            .join(",");
-        <String as AsExpression<Text>>::as_expression(text)
-    }
-}
-
-impl FromSql<Text, Sqlite> for MediaIdList {
-    fn from_sql(
-        bytes: <Sqlite as diesel::backend::Backend>::RawValue<'_>,
-    ) -> diesel::deserialize::Result<Self> {
-        let list = <String as FromSql<Text, Sqlite>>::from_sql(bytes)?
-            .split(',')
-            .map(|id| id.parse::<MediaId>())
-            .collect::<Result<Vec<MediaId>, _>>()?;
-        Ok(MediaIdList(list))
-    }
-}
-
 pub enum MediaOrMediaList {
     Media(Media),
     MediaList(MediaList),
+    /// A selection of cached medias, e.g. from the library page.
+    Medias(Vec<Media>),
 }
 
 impl MediaOrMediaList {
-    pub fn media_ids(&self) -> Box<[MediaId]> {
+    pub fn media_ids(
+        &self,
+        db_conn: &mut SqliteConnection,
+    ) -> Result<Box<[MediaId]>, diesel::result::Error> {
         match self {
-            Self::Media(media) => [media.id].into(),
-            Self::MediaList(media_list) => media_list.media_ids.0.clone().into(),
+            Self::Media(media) => Ok([media.id].into()),
+            Self::MediaList(media_list) => {
+                Ok(query_media_list_media_ids(db_conn, media_list.id)?.into())
+            }
+            Self::Medias(medias) => Ok(medias.iter().map(|media| media.id).collect()),
+        }
+    }
+
+    /// The media list the medias belong to, if any.
+    pub fn media_list_id(&self) -> Option<MediaListId> {
+        match self {
+            Self::Media(_) | Self::Medias(_) => None,
+            Self::MediaList(media_list) => Some(media_list.id),
         }
     }
 
@@ -187,6 +174,11 @@ impl MediaOrMediaList {
         match self {
             Self::Media(media) => media.duration.as_ref().cloned().unwrap_or_default().0,
             Self::MediaList(media_list) => media_list.total_duration.0,
+            Self::Medias(medias) => medias
+                .iter()
+                .filter_map(|media| media.duration)
+                .map(|duration| duration.0)
+                .sum(),
         }
     }
 }
@@ -203,7 +195,7 @@ impl From<MediaList> for MediaOrMediaList {
     }
 }
 
-#[derive(Clone, Queryable, Selectable, Debug, Serialize)]
+#[derive(Clone, Queryable, QueryableByName, Selectable, Debug, Serialize)]
 #[diesel(table_name = crate::schema::medias)]
 #[diesel(check_for_backend(diesel::sqlite::Sqlite))]
 pub struct Media {
@@ -217,6 +209,10 @@ pub struct Media {
     pub views: i32,
     pub alt_title: Option<String>,
     pub alt_artist: Option<String>,
+    pub album: Option<String>,
+    pub track: Option<i32>,
+    pub release_date: Option<String>,
+    pub extractor: Option<String>,
 }
 
 impl Media {
@@ -233,14 +229,13 @@ impl Media {
     }
 }
 
-#[derive(Queryable, Selectable, Debug)]
+#[derive(Queryable, Selectable, Debug, Serialize)]
 #[diesel(table_name = crate::schema::media_lists)]
 #[diesel(check_for_backend(diesel::sqlite::Sqlite))]
 pub struct MediaList {
     pub id: MediaListId,
     pub title: Option<String>,
     pub artist: Option<String>,
-    pub media_ids: MediaIdList,
     pub url: String,
     pub add_timestamp: PrimitiveDateTime,
     pub total_duration: DurationWrapper,
@@ -268,6 +263,10 @@ pub struct NewMedia<'a> {
     pub duration: Option<i32>,
     pub url: Cow<'a, str>,
     pub media_type: String,
+    pub album: Option<Cow<'a, str>>,
+    pub track: Option<i32>,
+    pub release_date: Option<Cow<'a, str>>,
+    pub extractor: Option<Cow<'a, str>>,
 }
 
 #[derive(Insertable)]
@@ -275,11 +274,156 @@ pub struct NewMedia<'a> {
 pub struct NewMediaList<'a> {
     pub title: Cow<'a, str>,
     pub artist: Cow<'a, str>,
-    pub media_ids: Cow<'a, str>,
     pub url: Cow<'a, str>,
     pub total_duration: i32,
 }
 
+#[derive(Insertable)]
+#[diesel(table_name = media_list_entries)]
+struct NewMediaListEntry {
+    media_list_id: MediaListId,
+    position: i32,
+    media_id: MediaId,
+}
+
+/// Column a media listing is sorted by.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum MediaSort {
+    Title,
+    Artist,
+    Views,
+    #[default]
+    Added,
+    Duration,
+}
+
+impl MediaSort {
+    pub const ALL: [Self; 5] = [
+        Self::Title,
+        Self::Artist,
+        Self::Views,
+        Self::Added,
+        Self::Duration,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Title => "title",
+            Self::Artist => "artist",
+            Self::Views => "views",
+            Self::Added => "added",
+            Self::Duration => "duration",
+        }
+    }
+
+    fn sql_expr(&self) -> &'static str {
+        match self {
+            Self::Title => "coalesce(alt_title, title)",
+            Self::Artist => "coalesce(alt_artist, artist)",
+            Self::Views => "views",
+            Self::Added => "CAST(strftime('%s', add_timestamp) AS INTEGER)",
+            Self::Duration => "coalesce(duration, 0)",
+        }
+    }
+
+    /// Sort key of `media`, used as a keyset pagination cursor.
+    pub fn key_of(&self, media: &Media) -> MediaSortKey {
+        match self {
+            Self::Title => MediaSortKey::Text(media.display_title().to_owned()),
+            Self::Artist => MediaSortKey::Text(media.display_artist().to_owned()),
+            Self::Views => MediaSortKey::Integer(media.views.into()),
+            Self::Added => MediaSortKey::Integer(media.add_timestamp.assume_utc().unix_timestamp()),
+            Self::Duration => MediaSortKey::Integer(
+                media
+                    .duration
+                    .map(|d| d.whole_seconds())
+                    .unwrap_or_default(),
+            ),
+        }
+    }
+
+    pub fn parse_key(&self, key: &str) -> Option<MediaSortKey> {
+        match self {
+            Self::Title | Self::Artist => Some(MediaSortKey::Text(key.to_string())),
+            Self::Views | Self::Added | Self::Duration => {
+                key.parse().ok().map(MediaSortKey::Integer)
+            }
+        }
+    }
+}
+
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum MediaSortKey {
+    Integer(i64),
+    Text(String),
+}
+
+impl Display for MediaSortKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Integer(key) => key.fmt(f),
+            Self::Text(key) => key.fmt(f),
+        }
+    }
+}
+
+/// List cached medias in `sort` order, starting after the `after` cursor.
+pub fn query_medias(
+    db_conn: &mut SqliteConnection,
+    sort: MediaSort,
+    descending: bool,
+    filter_media_type: Option<&str>,
+    after: Option<(MediaSortKey, MediaId)>,
+    limit: usize,
+) -> Result<Vec<Media>, diesel::result::Error> {
+    use diesel::dsl::sql;
+    let mut query = medias::table.select(Media::as_select()).into_boxed();
+    if let Some(filter_media_type) = filter_media_type {
+        query = query.filter(medias::media_type.eq(filter_media_type.to_string()));
+    }
+    if let Some((key, after_id)) = after {
+        let cmp = if descending { "<" } else { ">" };
+        let prefix = format!("({}, id) {cmp} (", sort.sql_expr());
+        query = match key {
+            MediaSortKey::Integer(key) => query.filter(
+                sql::<Bool>(&prefix)
+                    .bind::<BigInt, _>(key)
+                    .sql(", ")
+                    .bind::<Integer, _>(after_id)
+                    .sql(")"),
+            ),
+            MediaSortKey::Text(key) => query.filter(
+                sql::<Bool>(&prefix)
+                    .bind::<Text, _>(key)
+                    .sql(", ")
+                    .bind::<Integer, _>(after_id)
+                    .sql(")"),
+            ),
+        };
+    }
+    let direction = if descending { "DESC" } else { "ASC" };
+    query
+        .order(sql::<Bool>(&format!(
+            "{} {direction}, id {direction}",
+            sort.sql_expr()
+        )))
+        .limit(limit.try_into().unwrap_or(i64::MAX))
+        .load(db_conn)
+}
+
+/// Every distinct `media_type` of the cached medias.
+pub fn query_media_types(
+    db_conn: &mut SqliteConnection,
+) -> Result<Vec<String>, diesel::result::Error> {
+    use crate::schema::medias::dsl::*;
+    medias
+        .select(media_type)
+        .distinct()
+        .order(media_type.asc())
+        .load(db_conn)
+}
+
 pub fn query_media_with_id(
     db_conn: &mut SqliteConnection,
     media_id: MediaId,
@@ -350,14 +494,207 @@ pub fn insert_media(
         .get_result(db_conn)
 }
 
+/// Insert `media`, unless a media with the same url is already cached.
+///
+/// Resolvers may return a media with a different url than the one they
+/// were given (e.g. YouTube searches resolve to the url of the first hit),
+/// so this is checked again after resolving.
+pub fn insert_or_get_media(
+    db_conn: &mut SqliteConnection,
+    media: NewMedia,
+) -> Result<Media, diesel::result::Error> {
+    use crate::schema::medias::dsl::*;
+    let existing: Option<Media> = medias
+        .filter(url.eq(media.url.as_ref()))
+        .select(Media::as_select())
+        .first(db_conn)
+        .optional()?;
+    match existing {
+        Some(existing) => Ok(existing),
+        None => insert_media(db_conn, media),
+    }
+}
+
 pub fn insert_media_list(
     db_conn: &mut SqliteConnection,
     media_list: NewMediaList,
+    media_ids: &[MediaId],
 ) -> Result<MediaList, diesel::result::Error> {
+    db_conn.transaction(|db_conn| {
+        let media_list: MediaList = diesel::insert_into(media_lists::table)
+            .values(media_list)
+            .get_result(db_conn)?;
+        insert_media_list_entries(db_conn, media_list.id, media_ids)?;
+        Ok(media_list)
+    })
+}
+
+fn insert_media_list_entries(
+    db_conn: &mut SqliteConnection,
+    list_id: MediaListId,
+    media_ids: &[MediaId],
+) -> Result<usize, diesel::result::Error> {
+    let entries = media_ids
+        .iter()
+        .enumerate()
+        .map(|(position, media_id)| NewMediaListEntry {
+            media_list_id: list_id,
+            position: position as i32,
+            media_id: *media_id,
+        })
+        .collect::<Vec<_>>();
+    diesel::insert_into(media_list_entries::table)
+        .values(entries)
+        .execute(db_conn)
+}
+
+/// Replace the metadata and entries of a media list with freshly resolved
+/// ones. The url of the list is kept as is.
+pub fn replace_media_list(
+    db_conn: &mut SqliteConnection,
+    list_id: MediaListId,
+    new_media_list: NewMediaList,
+    media_ids: &[MediaId],
+) -> Result<MediaList, diesel::result::Error> {
+    db_conn.transaction(|db_conn| {
+        let media_list: MediaList = diesel::update(media_lists::table)
+            .filter(media_lists::id.eq(list_id))
+            .set((
+                media_lists::title.eq(new_media_list.title.as_ref()),
+                media_lists::artist.eq(new_media_list.artist.as_ref()),
+                media_lists::total_duration.eq(new_media_list.total_duration),
+            ))
+            .get_result(db_conn)?;
+        diesel::delete(media_list_entries::table)
+            .filter(media_list_entries::media_list_id.eq(list_id))
+            .execute(db_conn)?;
+        insert_media_list_entries(db_conn, list_id, media_ids)?;
+        Ok(media_list)
+    })
+}
+
+/// Medias added to and removed from a list by [`update_media_list_medias`].
+pub struct MediaListChanges {
+    pub added: Vec<Media>,
+    pub removed: Vec<Media>,
+}
+
+/// Replace the medias of a list with `medias`, in order.
+pub fn update_media_list_medias(
+    db_conn: &mut SqliteConnection,
+    list_id: MediaListId,
+    new_media_list: NewMediaList,
+    medias: &[Media],
+) -> Result<MediaListChanges, diesel::result::Error> {
+    db_conn.transaction(|db_conn| {
+        let old_medias = query_media_list_medias(db_conn, list_id)?;
+        let old_ids: HashSet<MediaId> = old_medias.iter().map(|media| media.id).collect();
+        let new_ids: HashSet<MediaId> = medias.iter().map(|media| media.id).collect();
+        let added = medias
+            .iter()
+            .filter(|media| !old_ids.contains(&media.id))
+            .cloned()
+            .collect();
+        let removed = old_medias
+            .into_iter()
+            .filter(|media| !new_ids.contains(&media.id))
+            .collect();
+
+        let media_ids = medias.iter().map(|media| media.id).collect::<Vec<_>>();
+        replace_media_list(db_conn, list_id, new_media_list, &media_ids)?;
+        Ok(MediaListChanges { added, removed })
+    })
+}
+
+/// Ids of the medias in a list, in order.
+pub fn query_media_list_media_ids(
+    db_conn: &mut SqliteConnection,
+    list_id: MediaListId,
+) -> Result<Vec<MediaId>, diesel::result::Error> {
+    use crate::schema::media_list_entries::dsl::*;
+    media_list_entries
+        .filter(media_list_id.eq(list_id))
+        .order(position.asc())
+        .select(media_id)
+        .load(db_conn)
+}
+
+pub fn query_media_list_medias(
+    db_conn: &mut SqliteConnection,
+    list_id: MediaListId,
+) -> Result<Vec<Media>, diesel::result::Error> {
+    media_list_entries::table
+        .inner_join(medias::table)
+        .filter(media_list_entries::media_list_id.eq(list_id))
+        .order(media_list_entries::position.asc())
+        .select(Media::as_select())
+        .load(db_conn)
+}
+
+/// Media lists containing `media_id`, each listed once.
+pub fn query_media_lists_with_media(
+    db_conn: &mut SqliteConnection,
+    media_id: MediaId,
+) -> Result<Vec<MediaList>, diesel::result::Error> {
+    let list_ids = media_list_entries::table
+        .filter(media_list_entries::media_id.eq(media_id))
+        .select(media_list_entries::media_list_id);
+    media_lists::table
+        .filter(media_lists::id.eq_any(list_ids))
+        .order(media_lists::id.asc())
+        .select(MediaList::as_select())
+        .load(db_conn)
+}
+
+pub fn query_media_list_with_id(
+    db_conn: &mut SqliteConnection,
+    list_id: MediaListId,
+) -> ResourceQueryResult<MediaList> {
     use crate::schema::media_lists::dsl::*;
-    diesel::insert_into(media_lists)
-        .values(media_list)
-        .get_result(db_conn)
+    media_lists
+        .filter(id.eq(list_id))
+        .select(MediaList::as_select())
+        .first(db_conn)
+        .optional()?
+        .ok_or(ResourceQueryError::ResourceNotFound(
+            ResourceType::MediaList,
+            list_id.into(),
+        ))
+}
+
+/// Turn free text into an FTS5 query matching every word as a prefix, so
+/// that user input can not contain FTS5 syntax.
+fn fts_query(query: &str) -> String {
+    query
+        .split_whitespace()
+        .map(|word| format!("\"{}\"*", word.replace('"', "\"\"")))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Search the titles and artists (including the alternative ones) of every
+/// cached media, best matches first.
+pub fn search_medias(
+    db_conn: &mut SqliteConnection,
+    query: &str,
+    offset: usize,
+    limit: usize,
+) -> Result<Vec<Media>, diesel::result::Error> {
+    let query = fts_query(query);
+    if query.is_empty() {
+        return Ok(Vec::new());
+    }
+    diesel::sql_query(
+        "SELECT medias.* FROM medias_fts \
+         JOIN medias ON medias.id = medias_fts.rowid \
+         WHERE medias_fts MATCH ? \
+         ORDER BY rank \
+         LIMIT ? OFFSET ?",
+    )
+    .bind::<Text, _>(query)
+    .bind::<BigInt, _>(limit as i64)
+    .bind::<BigInt, _>(offset as i64)
+    .load(db_conn)
 }
 
 pub fn increase_media_view_count(
@@ -398,3 +735,81 @@ pub fn update_media_alt_data(
         ))
         .get_result(db_conn)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::test_utils::{insert_test_media, TestDb};
+
+    #[test]
+    fn search_follows_metadata_changes() {
+        let db = TestDb::new();
+        let mut db_conn = db.pool.get().unwrap();
+        let media = insert_test_media(&mut db_conn, 10);
+        insert_test_media(&mut db_conn, 10);
+
+        let hits = search_medias(&mut db_conn, "titl artist", 0, 10).unwrap();
+        assert_eq!(hits.len(), 2);
+        let hits = search_medias(&mut db_conn, &media.title, 0, 10).unwrap();
+        assert_eq!(hits.first().map(|hit| hit.id), Some(media.id));
+
+        update_media_alt_data(&mut db_conn, media.id, "Renamed \"song\"", "someone").unwrap();
+        let hits = search_medias(&mut db_conn, "renam some", 0, 10).unwrap();
+        assert_eq!(
+            hits.iter().map(|hit| hit.id).collect::<Vec<_>>(),
+            [media.id]
+        );
+        assert!(search_medias(&mut db_conn, "\"song\" AND (", 0, 10).is_ok());
+        assert!(search_medias(&mut db_conn, "   ", 0, 10)
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn keyset_pagination_visits_every_media_once() {
+        let db = TestDb::new();
+        let mut db_conn = db.pool.get().unwrap();
+        let medias = [30, 10, 20, 10, 40].map(|duration| insert_test_media(&mut db_conn, duration));
+        // Listed under their display title and artist.
+        update_media_alt_data(&mut db_conn, medias[3].id, "a title", "zz artist").unwrap();
+        update_media_alt_data(&mut db_conn, medias[1].id, "zz title", "a artist").unwrap();
+
+        for sort in MediaSort::ALL {
+            for descending in [false, true] {
+                let mut seen = Vec::new();
+                let mut after = None;
+                loop {
+                    let page =
+                        query_medias(&mut db_conn, sort, descending, None, after, 2).unwrap();
+                    let Some(last) = page.last() else {
+                        break;
+                    };
+                    after = Some((sort.key_of(last), last.id));
+                    seen.extend(page.iter().map(|media| (sort.key_of(media), media.id)));
+                }
+                assert_eq!(seen.len(), 5, "{sort:?}");
+                let mut sorted = seen.clone();
+                sorted.sort_by(|a, b| match (&a.0, &b.0) {
+                    (MediaSortKey::Integer(x), MediaSortKey::Integer(y)) => {
+                        (x, a.1 .0).cmp(&(y, b.1 .0))
+                    }
+                    (MediaSortKey::Text(x), MediaSortKey::Text(y)) => (x, a.1 .0).cmp(&(y, b.1 .0)),
+                    _ => unreachable!(),
+                });
+                if descending {
+                    sorted.reverse();
+                }
+                assert_eq!(seen, sorted, "{sort:?}");
+            }
+        }
+        for (sort, media) in [
+            (MediaSort::Title, &medias[3]),
+            (MediaSort::Artist, &medias[1]),
+        ] {
+            let page = query_medias(&mut db_conn, sort, false, None, None, 1).unwrap();
+            assert_eq!(page[0].id, media.id, "{sort:?}");
+        }
+        let types = query_media_types(&mut db_conn).unwrap();
+        assert_eq!(types, ["local"]);
+    }
+}
@@ -1,26 +1,41 @@
 use self::{
+    job::JobId,
     media::{MediaId, MediaListId},
     playlist::PlaylistId,
     playlist_item::PlaylistItemId,
 };
 use anyhow::{Context, Result};
-use diesel::{r2d2::ConnectionManager, SqliteConnection};
+use diesel::{
+    connection::{AnsiTransactionManager, SimpleConnection, TransactionManager},
+    r2d2::{ConnectionManager, CustomizeConnection},
+    Connection, SqliteConnection,
+};
 use diesel_migrations::{embed_migrations, EmbeddedMigrations, MigrationHarness};
 use r2d2::Pool;
 use std::fmt::Display;
 use thiserror::Error;
 
+pub mod history;
+pub mod integrity;
+pub mod job;
 pub mod media;
 pub mod playlist;
 pub mod playlist_item;
+pub mod shuffle;
+pub mod stats;
 
 pub type SqliteConnectionPool = Pool<ConnectionManager<SqliteConnection>>;
 
 pub fn establish_connection() -> Result<SqliteConnectionPool> {
-    const MIGRATIONS: EmbeddedMigrations = embed_migrations!("./migrations");
     let db_url = std::env::var("DATABASE_URL").context("DATABASE_URL not specified")?;
+    create_connection_pool(&db_url)
+}
+
+pub fn create_connection_pool(db_url: &str) -> Result<SqliteConnectionPool> {
+    const MIGRATIONS: EmbeddedMigrations = embed_migrations!("./migrations");
     let db_conn = ConnectionManager::<SqliteConnection>::new(db_url);
     let db_pool = Pool::builder()
+        .connection_customizer(Box::new(ConnectionOptions))
         .build(db_conn)
         .context("unable to build DB connection pool")?;
     db_pool
@@ -30,6 +45,36 @@ pub fn establish_connection() -> Result<SqliteConnectionPool> {
     Ok(db_pool)
 }
 
+#[derive(Debug)]
+struct ConnectionOptions;
+
+impl CustomizeConnection<SqliteConnection, diesel::r2d2::Error> for ConnectionOptions {
+    fn on_acquire(&self, conn: &mut SqliteConnection) -> Result<(), diesel::r2d2::Error> {
+        // wait for concurrent writers instead of failing with SQLITE_BUSY, and
+        // enforce foreign keys, which SQLite leaves off by default
+        conn.batch_execute("PRAGMA busy_timeout = 10000; PRAGMA foreign_keys = ON;")
+            .map_err(diesel::r2d2::Error::QueryError)
+    }
+}
+
+/// Run `f` in a `BEGIN IMMEDIATE` transaction, so that concurrent writers
+/// are serialized before they read anything. When a transaction is already
+/// open, `f` runs in a savepoint of it instead.
+pub fn immediate_transaction<T, E, F>(db_conn: &mut SqliteConnection, f: F) -> Result<T, E>
+where
+    F: FnOnce(&mut SqliteConnection) -> Result<T, E>,
+    E: From<diesel::result::Error>,
+{
+    let depth = AnsiTransactionManager::transaction_manager_status_mut(db_conn)
+        .transaction_depth()
+        .map_err(E::from)?;
+    if depth.is_some() {
+        db_conn.transaction(f)
+    } else {
+        db_conn.immediate_transaction(f)
+    }
+}
+
 #[derive(Error, Debug)]
 pub enum ResourceQueryError {
     #[error("{}", match .1 {
@@ -57,6 +102,7 @@ pub enum ResourceType {
     Playlist,
     PlaylistItem,
     MediaList,
+    Job,
 }
 
 #[derive(Debug)]
@@ -110,8 +156,101 @@ impl From<MediaListId> for Option<ResourceId> {
     }
 }
 
+impl From<JobId> for ResourceId {
+    fn from(value: JobId) -> Self {
+        Self(value.0)
+    }
+}
+
+impl From<JobId> for Option<ResourceId> {
+    fn from(value: JobId) -> Self {
+        Some(ResourceId(value.0))
+    }
+}
+
 impl Display for ResourceId {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         self.0.fmt(f)
     }
 }
+
+#[cfg(test)]
+pub(crate) mod test_utils {
+    use super::{
+        create_connection_pool,
+        media::{insert_media, Media, NewMedia},
+        playlist::{append_to_playlist, create_empty_playlist, query_playlist_from_id, Playlist},
+        playlist_item::PlaylistItemId,
+        SqliteConnectionPool,
+    };
+    use diesel::SqliteConnection;
+    use std::{path::PathBuf, time::SystemTime};
+    use time::Duration;
+
+    /// A migrated database in a temporary file, deleted on drop.
+    pub struct TestDb {
+        pub pool: SqliteConnectionPool,
+        path: PathBuf,
+    }
+
+    impl TestDb {
+        pub fn new() -> Self {
+            let nanos = SystemTime::now()
+                .duration_since(SystemTime::UNIX_EPOCH)
+                .unwrap()
+                .as_nanos();
+            let path = std::env::temp_dir().join(format!("plst3-test-{nanos}.db"));
+            let pool = create_connection_pool(path.to_str().unwrap()).unwrap();
+            Self { pool, path }
+        }
+    }
+
+    impl Drop for TestDb {
+        fn drop(&mut self) {
+            std::fs::remove_file(&self.path).ok();
+        }
+    }
+
+    pub fn insert_test_media(db_conn: &mut SqliteConnection, duration: i32) -> Media {
+        static ID: std::sync::atomic::AtomicU32 = std::sync::atomic::AtomicU32::new(0);
+        let id = ID.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+        insert_media(
+            db_conn,
+            NewMedia {
+                title: format!("title {id}").into(),
+                artist: "artist".into(),
+                duration: Some(duration),
+                url: format!("file:///test-{id}.mp3").into(),
+                media_type: "local".into(),
+                album: None,
+                track: None,
+                release_date: None,
+                extractor: None,
+            },
+        )
+        .unwrap()
+    }
+
+    /// A playlist with one new media per item, lasting `durations` seconds.
+    pub async fn playlist_with_medias(
+        db_conn: &mut SqliteConnection,
+        durations: &[i32],
+    ) -> (Playlist, Vec<PlaylistItemId>) {
+        let media_ids = durations
+            .iter()
+            .map(|duration| insert_test_media(db_conn, *duration).id)
+            .collect::<Vec<_>>();
+        let playlist_id = create_empty_playlist(db_conn, "test").await.unwrap();
+        let item_ids = append_to_playlist(
+            db_conn,
+            playlist_id,
+            None,
+            &media_ids,
+            None,
+            Duration::seconds(durations.iter().copied().map(i64::from).sum()),
+        )
+        .unwrap();
+        let playlist = query_playlist_from_id(db_conn, playlist_id).unwrap();
+        (playlist, item_ids)
+    }
+}
@@ -1,10 +1,11 @@
-use crate::db::{ResourceQueryError, ResourceType};
+use crate::db::{immediate_transaction, ResourceQueryError, ResourceType};
 
 use super::{
-    media::{DurationWrapper, MediaId},
+    media::{DurationWrapper, Media, MediaId, MediaListId},
     playlist_item::{
-        insert_playlist_item, query_playlist_item, update_playlist_item_next_id,
-        update_playlist_item_prev_id, NewPlaylistItem, PlaylistItemId,
+        insert_playlist_item, query_playlist_item, query_playlist_items_in_order,
+        remove_playlist_item, update_playlist_item_next_id, update_playlist_item_prev_id,
+        NewPlaylistItem, PlaylistItemId,
     },
     ResourceQueryResult,
 };
@@ -13,13 +14,13 @@ use diesel::{
     expression::AsExpression,
     prelude::*,
     serialize::ToSql,
-    sql_types::Integer,
+    sql_types::{Integer, Text},
     sqlite::Sqlite,
     ExpressionMethods, Queryable, Selectable, SelectableHelper, SqliteConnection,
 };
 use sailfish::runtime::Render;
 use serde::{Deserialize, Serialize};
-use std::{fmt::Display, str::FromStr};
+use std::{collections::HashSet, fmt::Display, str::FromStr};
 use time::{Duration, PrimitiveDateTime};
 
 #[derive(
@@ -66,6 +67,118 @@ impl FromStr for PlaylistId {
     }
 }
 
+/// Where new medias are inserted into a playlist.
+#[derive(
+    Clone, Copy, PartialEq, Eq, Debug, Default, FromSqlRow, AsExpression, Serialize, Deserialize,
+)]
+#[diesel(sql_type = Text)]
+#[serde(rename_all = "kebab-case")]
+pub enum AddPosition {
+    #[default]
+    QueueNext,
+    AddToStart,
+    AddToEnd,
+}
+
+impl AddPosition {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::QueueNext => "queue-next",
+            Self::AddToStart => "add-to-start",
+            Self::AddToEnd => "add-to-end",
+        }
+    }
+}
+
+impl FromStr for AddPosition {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "queue-next" => Ok(Self::QueueNext),
+            "add-to-start" => Ok(Self::AddToStart),
+            "add-to-end" => Ok(Self::AddToEnd),
+            s => Err(anyhow::anyhow!("invalid add position: {s}")),
+        }
+    }
+}
+
+impl FromSql<Text, Sqlite> for AddPosition {
+    fn from_sql(
+        bytes: <Sqlite as diesel::backend::Backend>::RawValue<'_>,
+    ) -> diesel::deserialize::Result<Self> {
+        Ok(<String as FromSql<Text, Sqlite>>::from_sql(bytes)?.parse()?)
+    }
+}
+
+impl ToSql<Text, Sqlite> for AddPosition {
+    fn to_sql<'b>(
+        &'b self,
+        out: &mut diesel::serialize::Output<'b, '_, Sqlite>,
+    ) -> diesel::serialize::Result {
+        <str as ToSql<Text, Sqlite>>::to_sql(self.as_str(), out)
+    }
+}
+
+/// What happens when the last item of a playlist, or any item for
+/// [`RepeatMode::One`], is done playing.
+#[derive(
+    Clone, Copy, PartialEq, Eq, Debug, Default, FromSqlRow, AsExpression, Serialize, Deserialize,
+)]
+#[diesel(sql_type = Text)]
+#[serde(rename_all = "kebab-case")]
+pub enum RepeatMode {
+    /// Wrap around to the other end of the playlist.
+    #[default]
+    All,
+    /// Play the current item again.
+    One,
+    /// Stop at the end of the playlist.
+    None,
+}
+
+impl RepeatMode {
+    pub const ALL: [Self; 3] = [Self::All, Self::One, Self::None];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::All => "all",
+            Self::One => "one",
+            Self::None => "none",
+        }
+    }
+}
+
+impl FromStr for RepeatMode {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "all" => Ok(Self::All),
+            "one" => Ok(Self::One),
+            "none" => Ok(Self::None),
+            s => Err(anyhow::anyhow!("invalid repeat mode: {s}")),
+        }
+    }
+}
+
+impl FromSql<Text, Sqlite> for RepeatMode {
+    fn from_sql(
+        bytes: <Sqlite as diesel::backend::Backend>::RawValue<'_>,
+    ) -> diesel::deserialize::Result<Self> {
+        Ok(<String as FromSql<Text, Sqlite>>::from_sql(bytes)?.parse()?)
+    }
+}
+
+impl ToSql<Text, Sqlite> for RepeatMode {
+    fn to_sql<'b>(
+        &'b self,
+        out: &mut diesel::serialize::Output<'b, '_, Sqlite>,
+    ) -> diesel::serialize::Result {
+        <str as ToSql<Text, Sqlite>>::to_sql(self.as_str(), out)
+    }
+}
+
 #[derive(Queryable, Selectable, Debug)]
 #[diesel(table_name = crate::schema::playlists)]
 #[diesel(check_for_backend(diesel::sqlite::Sqlite))]
@@ -78,6 +191,11 @@ pub struct Playlist {
     pub current_item: Option<PlaylistItemId>,
     pub total_duration: DurationWrapper,
     pub num_items: i32,
+    pub repeat_mode: RepeatMode,
+    /// Stop once the current item is done playing, then reset.
+    pub stop_after_current: bool,
+    /// Play the items in a random order, without reordering them.
+    pub shuffle: bool,
 }
 
 pub fn query_playlist_from_id(
@@ -126,28 +244,32 @@ pub fn append_to_playlist(
     playlist_id: PlaylistId,
     prev: Option<PlaylistItemId>,
     media_ids: &[MediaId],
+    media_list_id: Option<MediaListId>,
     total_duration: Duration,
 ) -> ResourceQueryResult<Vec<PlaylistItemId>> {
-    let next = match prev {
-        Some(id) => query_playlist_item(db_conn, id)?.next,
-        None => query_playlist_from_id(db_conn, playlist_id)?.first_playlist_item,
-    };
-    let mut item_ids = vec![];
-    for media_id in media_ids.iter().cloned() {
-        item_ids.push(append_to_playlist_single(
-            db_conn,
-            NewPlaylistItem {
-                playlist_id,
-                media_id,
-                prev: item_ids.last().cloned().or(prev),
-                next,
-            },
-        )?);
-    }
-    if !item_ids.is_empty() {
-        update_playlist(db_conn, playlist_id, total_duration, media_ids.len() as i32)?;
-    }
-    Ok(item_ids)
+    immediate_transaction(db_conn, |db_conn| {
+        let next = match prev {
+            Some(id) => query_playlist_item(db_conn, id)?.next,
+            None => query_playlist_from_id(db_conn, playlist_id)?.first_playlist_item,
+        };
+        let mut item_ids = vec![];
+        for media_id in media_ids.iter().cloned() {
+            item_ids.push(append_to_playlist_single(
+                db_conn,
+                NewPlaylistItem {
+                    playlist_id,
+                    media_id,
+                    prev: item_ids.last().cloned().or(prev),
+                    next,
+                    media_list_id,
+                },
+            )?);
+        }
+        if !item_ids.is_empty() {
+            update_playlist(db_conn, playlist_id, total_duration, media_ids.len() as i32)?;
+        }
+        Ok(item_ids)
+    })
 }
 
 pub fn update_playlist_first_item(
@@ -204,6 +326,44 @@ pub(crate) fn update_playlist_current_item(
         })
 }
 
+pub fn update_playlist_repeat_mode(
+    db_conn: &mut SqliteConnection,
+    playlist_id: PlaylistId,
+    mode: RepeatMode,
+) -> ResourceQueryResult<()> {
+    use crate::schema::playlists::dsl::*;
+    let updated = diesel::update(playlists)
+        .filter(id.eq(playlist_id))
+        .set(repeat_mode.eq(mode))
+        .execute(db_conn)?;
+    if updated == 0 {
+        return Err(ResourceQueryError::ResourceNotFound(
+            ResourceType::Playlist,
+            playlist_id.into(),
+        ));
+    }
+    Ok(())
+}
+
+pub fn update_playlist_stop_after_current(
+    db_conn: &mut SqliteConnection,
+    playlist_id: PlaylistId,
+    stop: bool,
+) -> ResourceQueryResult<()> {
+    use crate::schema::playlists::dsl::*;
+    let updated = diesel::update(playlists)
+        .filter(id.eq(playlist_id))
+        .set(stop_after_current.eq(stop))
+        .execute(db_conn)?;
+    if updated == 0 {
+        return Err(ResourceQueryError::ResourceNotFound(
+            ResourceType::Playlist,
+            playlist_id.into(),
+        ));
+    }
+    Ok(())
+}
+
 pub async fn create_empty_playlist(
     db_conn: &mut SqliteConnection,
     playlist_title: &str,
@@ -284,3 +444,188 @@ pub fn delete_playlist(
         })?;
     Ok(())
 }
+
+/// Remove the items of `removed` medias that were imported from the list,
+/// and insert the `added` medias right after the remaining ones (or at the
+/// end of the playlist if there are none left).
+///
+/// Returns whether the current item of the playlist was removed.
+pub fn sync_media_list_into_playlist(
+    db_conn: &mut SqliteConnection,
+    playlist_id: PlaylistId,
+    list_id: MediaListId,
+    added: &[Media],
+    removed: &[Media],
+) -> ResourceQueryResult<bool> {
+    immediate_transaction(db_conn, |db_conn| {
+        let removed_ids: HashSet<MediaId> = removed.iter().map(|media| media.id).collect();
+        let playlist = query_playlist_from_id(db_conn, playlist_id)?;
+        let items =
+            query_playlist_items_in_order(db_conn, playlist_id, playlist.first_playlist_item)?;
+        let mut media_changed = false;
+        let mut last_list_item = None;
+        for item in items
+            .into_iter()
+            .filter(|item| item.media_list_id == Some(list_id))
+        {
+            if removed_ids.contains(&item.media_id) {
+                media_changed |= remove_playlist_item(db_conn, item.id)?;
+            } else {
+                last_list_item = Some(item.id);
+            }
+        }
+
+        let pivot = match last_list_item {
+            Some(item_id) => Some(item_id),
+            None => query_playlist_from_id(db_conn, playlist_id)?.last_playlist_item,
+        };
+        let media_ids = added.iter().map(|media| media.id).collect::<Vec<_>>();
+        let total_duration = added
+            .iter()
+            .filter_map(|media| media.duration.as_ref())
+            .map(|duration| duration.0)
+            .sum();
+        append_to_playlist(
+            db_conn,
+            playlist_id,
+            pivot,
+            &media_ids,
+            Some(list_id),
+            total_duration,
+        )?;
+        Ok(media_changed)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::{
+        integrity::check_playlist,
+        media::{
+            insert_media_list, query_media_list_medias, update_media_list_medias, NewMediaList,
+        },
+        test_utils::{insert_test_media, TestDb},
+    };
+
+    #[tokio::test]
+    async fn delete_playlist_cascades_to_items() {
+        let db = TestDb::new();
+        let mut db_conn = db.pool.get().unwrap();
+        let media = insert_test_media(&mut db_conn, 10);
+        let playlist_id = create_empty_playlist(&mut db_conn, "test").await.unwrap();
+        let item_ids = append_to_playlist(
+            &mut db_conn,
+            playlist_id,
+            None,
+            &[media.id; 3],
+            None,
+            Duration::seconds(30),
+        )
+        .unwrap();
+
+        delete_playlist(&mut db_conn, playlist_id).unwrap();
+        for item_id in item_ids {
+            assert!(matches!(
+                query_playlist_item(&mut db_conn, item_id),
+                Err(ResourceQueryError::ResourceNotFound(_, _))
+            ));
+        }
+    }
+
+    #[test]
+    fn repeat_settings_of_missing_playlist_are_not_found() {
+        let db = TestDb::new();
+        let mut db_conn = db.pool.get().unwrap();
+        assert!(matches!(
+            update_playlist_repeat_mode(&mut db_conn, PlaylistId(42), RepeatMode::One),
+            Err(ResourceQueryError::ResourceNotFound(_, _))
+        ));
+        assert!(matches!(
+            update_playlist_stop_after_current(&mut db_conn, PlaylistId(42), true),
+            Err(ResourceQueryError::ResourceNotFound(_, _))
+        ));
+    }
+
+    #[tokio::test]
+    async fn updated_media_lists_are_synced_into_playlists() {
+        let db = TestDb::new();
+        let mut db_conn = db.pool.get().unwrap();
+        let [other, a, b, c, d] =
+            [10, 20, 30, 40, 50].map(|duration| insert_test_media(&mut db_conn, duration));
+        let new_list = || NewMediaList {
+            title: "list".into(),
+            artist: "artist".into(),
+            url: "file:///list.m3u".into(),
+            total_duration: 0,
+        };
+        let list = insert_media_list(&mut db_conn, new_list(), &[a.id, b.id, c.id]).unwrap();
+
+        // other, a, b, c, other
+        let playlist_id = create_empty_playlist(&mut db_conn, "test").await.unwrap();
+        let first = append_to_playlist(
+            &mut db_conn,
+            playlist_id,
+            None,
+            &[other.id],
+            None,
+            Duration::seconds(10),
+        )
+        .unwrap();
+        let list_items = append_to_playlist(
+            &mut db_conn,
+            playlist_id,
+            Some(first[0]),
+            &[a.id, b.id, c.id],
+            Some(list.id),
+            Duration::seconds(90),
+        )
+        .unwrap();
+        append_to_playlist(
+            &mut db_conn,
+            playlist_id,
+            Some(list_items[2]),
+            &[other.id],
+            None,
+            Duration::seconds(10),
+        )
+        .unwrap();
+        update_playlist_current_item(&mut db_conn, playlist_id, Some(list_items[1])).unwrap();
+
+        let changes = update_media_list_medias(
+            &mut db_conn,
+            list.id,
+            new_list(),
+            &[d.clone(), a.clone(), c.clone()],
+        )
+        .unwrap();
+        let ids = |medias: &[Media]| medias.iter().map(|media| media.id).collect::<Vec<_>>();
+        assert_eq!(ids(&changes.added), [d.id]);
+        assert_eq!(ids(&changes.removed), [b.id]);
+        assert_eq!(
+            ids(&query_media_list_medias(&mut db_conn, list.id).unwrap()),
+            [d.id, a.id, c.id]
+        );
+
+        let media_changed = sync_media_list_into_playlist(
+            &mut db_conn,
+            playlist_id,
+            list.id,
+            &changes.added,
+            &changes.removed,
+        )
+        .unwrap();
+        assert!(media_changed);
+        assert_eq!(check_playlist(&mut db_conn, playlist_id).unwrap(), vec![]);
+        let playlist = query_playlist_from_id(&mut db_conn, playlist_id).unwrap();
+        let order =
+            query_playlist_items_in_order(&mut db_conn, playlist_id, playlist.first_playlist_item)
+                .unwrap()
+                .into_iter()
+                .map(|item| item.media_id)
+                .collect::<Vec<_>>();
+        assert_eq!(order, [other.id, a.id, c.id, d.id, other.id]);
+        assert_eq!(playlist.current_item, None);
+        assert_eq!(playlist.total_duration.0, Duration::seconds(130));
+    }
+}
@@ -1,4 +1,4 @@
-use std::{fmt::Display, str::FromStr};
+use std::{collections::HashMap, fmt::Display, str::FromStr};
 
 use anyhow::Result;
 use diesel::{
@@ -10,17 +10,18 @@ use diesel::{
     sqlite::Sqlite,
     ExpressionMethods, Queryable, Selectable, SelectableHelper, SqliteConnection,
 };
+use rand::{seq::SliceRandom, Rng};
 use sailfish::runtime::Render;
 use serde::{Deserialize, Serialize};
 use time::PrimitiveDateTime;
 
-use crate::db::{ResourceQueryError, ResourceType};
+use crate::db::{immediate_transaction, ResourceQueryError, ResourceType};
 
 use super::{
-    media::{query_media_with_id, MediaId},
+    media::{query_media_with_id, MediaId, MediaListId},
     playlist::{
-        update_playlist, update_playlist_current_item, update_playlist_first_item,
-        update_playlist_last_item, PlaylistId,
+        query_playlist_from_id, update_playlist, update_playlist_current_item,
+        update_playlist_first_item, update_playlist_last_item, PlaylistId,
     },
     ResourceQueryResult,
 };
@@ -79,6 +80,8 @@ pub struct PlaylistItem {
     pub prev: Option<PlaylistItemId>,
     pub next: Option<PlaylistItemId>,
     pub add_timestamp: PrimitiveDateTime,
+    /// The media list this item was imported from, if any.
+    pub media_list_id: Option<MediaListId>,
 }
 
 #[derive(Insertable)]
@@ -88,6 +91,7 @@ pub struct NewPlaylistItem {
     pub media_id: MediaId,
     pub prev: Option<PlaylistItemId>,
     pub next: Option<PlaylistItemId>,
+    pub media_list_id: Option<MediaListId>,
 }
 
 pub fn query_playlist_item(
@@ -110,6 +114,30 @@ pub fn query_playlist_item(
     }
 }
 
+/// Query every item of a playlist, in playlist order.
+pub fn query_playlist_items_in_order(
+    db_conn: &mut SqliteConnection,
+    pid: PlaylistId,
+    first_item: Option<PlaylistItemId>,
+) -> ResourceQueryResult<Vec<PlaylistItem>> {
+    use crate::schema::playlist_items::dsl::*;
+    let mut items: HashMap<PlaylistItemId, PlaylistItem> = playlist_items
+        .filter(playlist_id.eq(pid))
+        .select(PlaylistItem::as_select())
+        .load(db_conn)?
+        .into_iter()
+        .map(|item| (item.id, item))
+        .collect();
+    let mut ordered = Vec::with_capacity(items.len());
+    let mut next_id = first_item;
+    // removing visited items also guards against cycles
+    while let Some(item) = next_id.and_then(|item_id| items.remove(&item_id)) {
+        next_id = item.next;
+        ordered.push(item);
+    }
+    Ok(ordered)
+}
+
 pub fn insert_playlist_item(
     db_conn: &mut SqliteConnection,
     item: NewPlaylistItem,
@@ -180,41 +208,47 @@ pub fn remove_playlist_item(
     db_conn: &mut SqliteConnection,
     item_id: PlaylistItemId,
 ) -> ResourceQueryResult<bool> {
-    let item = query_playlist_item(db_conn, item_id)?;
-    if let Some(prev) = item.prev {
-        update_playlist_item_next_id(db_conn, prev, item.next)?;
-    } else {
-        update_playlist_first_item(db_conn, item.playlist_id, item.next)?;
-    }
-    if let Some(next) = item.next {
-        update_playlist_item_prev_id(db_conn, next, item.prev)?;
-    } else {
-        update_playlist_last_item(db_conn, item.playlist_id, item.prev)?;
-    }
-    let media = query_media_with_id(db_conn, item.media_id)?;
-    let playlist = update_playlist(
-        db_conn,
-        item.playlist_id,
-        -media.duration.unwrap_or_default().0,
-        -1,
-    )?;
-    let media_changed = playlist.current_item == Some(item_id);
-    if media_changed {
-        update_playlist_current_item(db_conn, playlist.id, None)?;
-    }
+    immediate_transaction(db_conn, |db_conn| {
+        let item = query_playlist_item(db_conn, item_id)?;
+        if let Some(prev) = item.prev {
+            update_playlist_item_next_id(db_conn, prev, item.next)?;
+        } else {
+            update_playlist_first_item(db_conn, item.playlist_id, item.next)?;
+        }
+        if let Some(next) = item.next {
+            update_playlist_item_prev_id(db_conn, next, item.prev)?;
+        } else {
+            update_playlist_last_item(db_conn, item.playlist_id, item.prev)?;
+        }
+        let media = query_media_with_id(db_conn, item.media_id)?;
+        let playlist = update_playlist(
+            db_conn,
+            item.playlist_id,
+            -media.duration.unwrap_or_default().0,
+            -1,
+        )?;
+        let media_changed = playlist.current_item == Some(item_id);
+        if media_changed {
+            update_playlist_current_item(db_conn, playlist.id, None)?;
+        }
 
-    {
-        use crate::schema::playlist_items::dsl::*;
-        diesel::delete(playlist_items)
-            .filter(id.eq_all(item_id))
-            .execute(db_conn)
-            .map_err(|e| {
-                ResourceQueryError::db_error_if_not_not_found(e).unwrap_or_else(|| {
-                    ResourceQueryError::ResourceNotFound(ResourceType::PlaylistItem, item_id.into())
-                })
-            })?;
-    }
-    Ok(media_changed)
+        {
+            use crate::schema::playlist_items::dsl::*;
+            diesel::delete(playlist_items)
+                .filter(id.eq_all(item_id))
+                .execute(db_conn)
+                .map_err(|e| {
+                    ResourceQueryError::db_error_if_not_not_found(e).unwrap_or_else(|| {
+                        ResourceQueryError::ResourceNotFound(
+                            ResourceType::PlaylistItem,
+                            item_id.into(),
+                        )
+                    })
+                })?;
+        }
+
+        Ok(media_changed)
+    })
 }
 
 pub fn playlist_items_with_media_id(
@@ -228,3 +262,482 @@ pub fn playlist_items_with_media_id(
         .load(db_conn)?
         .into())
 }
+
+/// Items of any playlist that were imported from `list_id`.
+pub fn playlist_items_with_media_list_id(
+    db_conn: &mut SqliteConnection,
+    list_id: MediaListId,
+) -> ResourceQueryResult<Box<[PlaylistItem]>> {
+    use crate::schema::playlist_items::dsl::*;
+    Ok(playlist_items
+        .filter(media_list_id.eq(list_id))
+        .select(PlaylistItem::as_select())
+        .load(db_conn)?
+        .into())
+}
+
+#[derive(Clone, Debug)]
+struct PlaylistItemRange {
+    first: PlaylistItemId,
+    last: PlaylistItemId,
+}
+
+/// Group `ids` into maximal runs of consecutive playlist items.
+fn partition_ids_into_ranges(
+    db_conn: &mut SqliteConnection,
+    ids: &[PlaylistItemId],
+) -> ResourceQueryResult<Vec<PlaylistItemRange>> {
+    let mut range_dict = HashMap::new();
+    let mut items = Vec::new();
+    for id in ids {
+        let item = query_playlist_item(db_conn, *id)?;
+        range_dict.insert(
+            *id,
+            PlaylistItemRange {
+                first: *id,
+                last: *id,
+            },
+        );
+        items.push(item);
+    }
+
+    for item in items {
+        if let Some(prev_item) = item.prev.as_ref() {
+            let prev_range = range_dict.get(prev_item);
+            let cur_range = range_dict.get(&item.id);
+
+            if let Some((prev_range, cur_range)) = prev_range.zip(cur_range) {
+                // merge prev_range and cur_range
+                let merged_range = PlaylistItemRange {
+                    first: prev_range.first,
+                    last: cur_range.last,
+                };
+
+                range_dict.remove(prev_item);
+                range_dict.remove(&item.id);
+                range_dict.insert(merged_range.first, merged_range.clone());
+                range_dict.insert(merged_range.last, merged_range.clone());
+            }
+        }
+    }
+
+    let ranges = range_dict
+        .into_iter()
+        .filter(|(id, range)| *id == range.first)
+        .map(|(_, range)| range)
+        .collect();
+    Ok(ranges)
+}
+
+/// Swap every run of consecutive items in `ids` with the item after it.
+pub fn move_playlist_items_up(
+    db_conn: &mut SqliteConnection,
+    playlist_id: PlaylistId,
+    ids: &[PlaylistItemId],
+) -> ResourceQueryResult<()> {
+    immediate_transaction(db_conn, |db_conn| {
+        for range in partition_ids_into_ranges(db_conn, ids)? {
+            let PlaylistItemRange { first, last } = range;
+            let prev = query_playlist_item(db_conn, first)?.prev;
+            let next = query_playlist_item(db_conn, last)?.next;
+            if let Some(next) = next {
+                let next_next = query_playlist_item(db_conn, next)?.next;
+                update_playlist_item_prev_and_next_id(db_conn, next, prev, Some(first))?;
+                update_playlist_item_prev_id(db_conn, first, Some(next))?;
+                update_playlist_item_next_id(db_conn, last, next_next)?;
+                if let Some(next_next) = next_next {
+                    update_playlist_item_prev_id(db_conn, next_next, Some(last))?;
+                } else {
+                    update_playlist_last_item(db_conn, playlist_id, Some(last))?;
+                }
+                if let Some(prev) = prev {
+                    update_playlist_item_next_id(db_conn, prev, Some(next))?;
+                } else {
+                    update_playlist_first_item(db_conn, playlist_id, Some(next))?;
+                }
+            }
+        }
+        Ok(())
+    })
+}
+
+/// Swap every run of consecutive items in `ids` with the item before it.
+pub fn move_playlist_items_down(
+    db_conn: &mut SqliteConnection,
+    playlist_id: PlaylistId,
+    ids: &[PlaylistItemId],
+) -> ResourceQueryResult<()> {
+    immediate_transaction(db_conn, |db_conn| {
+        for range in partition_ids_into_ranges(db_conn, ids)? {
+            let PlaylistItemRange { first, last } = range;
+            let prev = query_playlist_item(db_conn, first)?.prev;
+            let next = query_playlist_item(db_conn, last)?.next;
+            if let Some(prev) = prev {
+                let prev_prev = query_playlist_item(db_conn, prev)?.prev;
+                update_playlist_item_prev_and_next_id(db_conn, prev, Some(last), next)?;
+                update_playlist_item_next_id(db_conn, last, Some(prev))?;
+                update_playlist_item_prev_id(db_conn, first, prev_prev)?;
+                if let Some(prev_prev) = prev_prev {
+                    update_playlist_item_next_id(db_conn, prev_prev, Some(first))?;
+                } else {
+                    update_playlist_first_item(db_conn, playlist_id, Some(first))?;
+                }
+                if let Some(next) = next {
+                    update_playlist_item_prev_id(db_conn, next, Some(prev))?;
+                } else {
+                    update_playlist_last_item(db_conn, playlist_id, Some(prev))?;
+                }
+            }
+        }
+        Ok(())
+    })
+}
+
+/// Where [`move_playlist_items`] moves the selected items to.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum MoveTarget {
+    Start,
+    End,
+    After(PlaylistItemId),
+}
+
+/// Move the items of `ids` to `target`, keeping their relative order. If the
+/// target item is itself selected, the items are moved after the closest
+/// unselected item before it instead.
+pub fn move_playlist_items(
+    db_conn: &mut SqliteConnection,
+    playlist_id: PlaylistId,
+    ids: &[PlaylistItemId],
+    target: MoveTarget,
+) -> ResourceQueryResult<()> {
+    immediate_transaction(db_conn, |db_conn| {
+        let playlist = query_playlist_from_id(db_conn, playlist_id)?;
+        let positions: HashMap<PlaylistItemId, usize> =
+            query_playlist_items_in_order(db_conn, playlist_id, playlist.first_playlist_item)?
+                .iter()
+                .enumerate()
+                .map(|(index, item)| (item.id, index))
+                .collect();
+        let not_found = |id: PlaylistItemId| {
+            ResourceQueryError::ResourceNotFound(ResourceType::PlaylistItem, id.into())
+        };
+        if let Some(id) = ids.iter().find(|id| !positions.contains_key(id)) {
+            return Err(not_found(*id));
+        }
+        let mut anchor = match target {
+            MoveTarget::Start => None,
+            MoveTarget::End => playlist.last_playlist_item,
+            MoveTarget::After(item_id) if positions.contains_key(&item_id) => Some(item_id),
+            MoveTarget::After(item_id) => return Err(not_found(item_id)),
+        };
+        while let Some(item_id) = anchor.filter(|item_id| ids.contains(item_id)) {
+            anchor = query_playlist_item(db_conn, item_id)?.prev;
+        }
+
+        let mut ranges = partition_ids_into_ranges(db_conn, ids)?;
+        ranges.sort_by_key(|range| positions[&range.first]);
+        for PlaylistItemRange { first, last } in ranges.iter().cloned() {
+            let prev = query_playlist_item(db_conn, first)?.prev;
+            let next = query_playlist_item(db_conn, last)?.next;
+            match prev {
+                Some(prev) => update_playlist_item_next_id(db_conn, prev, next)?,
+                None => update_playlist_first_item(db_conn, playlist_id, next)?,
+            }
+            match next {
+                Some(next) => update_playlist_item_prev_id(db_conn, next, prev)?,
+                None => update_playlist_last_item(db_conn, playlist_id, prev)?,
+            }
+        }
+        for PlaylistItemRange { first, last } in ranges {
+            let next = match anchor {
+                Some(anchor) => query_playlist_item(db_conn, anchor)?.next,
+                None => query_playlist_from_id(db_conn, playlist_id)?.first_playlist_item,
+            };
+            update_playlist_item_prev_id(db_conn, first, anchor)?;
+            update_playlist_item_next_id(db_conn, last, next)?;
+            match anchor {
+                Some(anchor) => update_playlist_item_next_id(db_conn, anchor, Some(first))?,
+                None => update_playlist_first_item(db_conn, playlist_id, Some(first))?,
+            }
+            match next {
+                Some(next) => update_playlist_item_prev_id(db_conn, next, Some(last))?,
+                None => update_playlist_last_item(db_conn, playlist_id, Some(last))?,
+            }
+            anchor = Some(last);
+        }
+        Ok(())
+    })
+}
+
+/// Randomly reorder the items of a playlist. If `after_current` is set, only
+/// the items after the current one are shuffled.
+pub fn shuffle_playlist_items(
+    db_conn: &mut SqliteConnection,
+    playlist_id: PlaylistId,
+    after_current: bool,
+    rng: &mut impl Rng,
+) -> ResourceQueryResult<()> {
+    immediate_transaction(db_conn, |db_conn| {
+        let playlist = query_playlist_from_id(db_conn, playlist_id)?;
+        let mut ids =
+            query_playlist_items_in_order(db_conn, playlist_id, playlist.first_playlist_item)?
+                .into_iter()
+                .map(|item| item.id)
+                .collect::<Vec<_>>();
+        let start = match playlist.current_item.filter(|_| after_current) {
+            Some(current) => ids
+                .iter()
+                .position(|id| *id == current)
+                .map_or(ids.len(), |index| index + 1),
+            None => 0,
+        };
+        ids[start..].shuffle(rng);
+
+        // the item before the shuffled ones only needs its next id updated
+        if let Some(prev) = start.checked_sub(1).map(|index| ids[index]) {
+            update_playlist_item_next_id(db_conn, prev, ids.get(start).copied())?;
+        }
+        for index in start..ids.len() {
+            let prev = index.checked_sub(1).map(|index| ids[index]);
+            let next = ids.get(index + 1).copied();
+            update_playlist_item_prev_and_next_id(db_conn, ids[index], prev, next)?;
+        }
+        if start == 0 {
+            update_playlist_first_item(db_conn, playlist_id, ids.first().copied())?;
+        }
+        update_playlist_last_item(db_conn, playlist_id, ids.last().copied())?;
+        Ok(())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::{
+        playlist::{append_to_playlist, create_empty_playlist, query_playlist_from_id},
+        test_utils::{insert_test_media, TestDb},
+    };
+    use std::collections::HashSet;
+    use time::Duration;
+
+    /// Tiny xorshift generator, so that each worker picks different items.
+    struct Rng(u64);
+
+    impl Rng {
+        fn next(&mut self, bound: usize) -> usize {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            (self.0 % bound as u64) as usize
+        }
+    }
+
+    fn item_ids(db_conn: &mut SqliteConnection, pid: PlaylistId) -> Vec<PlaylistItemId> {
+        use crate::schema::playlist_items::dsl::*;
+        playlist_items
+            .filter(playlist_id.eq(pid))
+            .select(id)
+            .load(db_conn)
+            .unwrap()
+    }
+
+    fn ignore_not_found<T>(result: ResourceQueryResult<T>) {
+        match result {
+            Ok(_) | Err(ResourceQueryError::ResourceNotFound(_, _)) => {}
+            Err(e) => panic!("{e}"),
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn concurrent_mutations_keep_playlist_intact() {
+        let db = TestDb::new();
+        let db_pool = db.pool.clone();
+        let mut db_conn = db_pool.get().unwrap();
+        let media = insert_test_media(&mut db_conn, 10);
+        let pid = create_empty_playlist(&mut db_conn, "test").await.unwrap();
+
+        let workers = (0..4u64)
+            .map(|worker| {
+                let db_pool = db_pool.clone();
+                tokio::task::spawn_blocking(move || {
+                    let mut db_conn = db_pool.get().unwrap();
+                    let mut rng = Rng(worker * 7919 + 1);
+                    for _ in 0..50 {
+                        let ids = item_ids(&mut db_conn, pid);
+                        let pick = (!ids.is_empty()).then(|| ids[rng.next(ids.len())]);
+                        match rng.next(4) {
+                            0 | 1 => ignore_not_found(append_to_playlist(
+                                &mut db_conn,
+                                pid,
+                                pick,
+                                &[media.id, media.id],
+                                None,
+                                Duration::seconds(20),
+                            )),
+                            2 => ignore_not_found(move_playlist_items_up(
+                                &mut db_conn,
+                                pid,
+                                &pick.into_iter().collect::<Vec<_>>(),
+                            )),
+                            _ => {
+                                if let Some(pick) = pick {
+                                    ignore_not_found(move_playlist_items_down(
+                                        &mut db_conn,
+                                        pid,
+                                        &[pick],
+                                    ));
+                                    ignore_not_found(remove_playlist_item(&mut db_conn, pick));
+                                }
+                            }
+                        }
+                    }
+                })
+            })
+            .collect::<Vec<_>>();
+        for worker in workers {
+            worker.await.unwrap();
+        }
+
+        let playlist = query_playlist_from_id(&mut db_conn, pid).unwrap();
+        let items =
+            query_playlist_items_in_order(&mut db_conn, pid, playlist.first_playlist_item).unwrap();
+        let all_ids = item_ids(&mut db_conn, pid);
+        assert_eq!(items.len(), all_ids.len(), "some items are unreachable");
+        assert_eq!(
+            items
+                .iter()
+                .map(|item| item.id)
+                .collect::<HashSet<_>>()
+                .len(),
+            items.len()
+        );
+        assert_eq!(playlist.num_items as usize, items.len());
+        assert_eq!(
+            playlist.total_duration.whole_seconds(),
+            10 * items.len() as i64
+        );
+        assert_eq!(
+            playlist.last_playlist_item,
+            items.last().map(|item| item.id)
+        );
+        let mut prev = None;
+        for item in &items {
+            assert_eq!(item.prev, prev);
+            prev = Some(item.id);
+        }
+    }
+
+    #[tokio::test]
+    async fn shuffle_after_current_keeps_played_items() {
+        use crate::db::integrity::check_playlist;
+        use rand::{rngs::StdRng, SeedableRng};
+
+        let db = TestDb::new();
+        let mut db_conn = db.pool.get().unwrap();
+        let media = insert_test_media(&mut db_conn, 10);
+        let pid = create_empty_playlist(&mut db_conn, "test").await.unwrap();
+        let ids = append_to_playlist(
+            &mut db_conn,
+            pid,
+            None,
+            &[media.id; 20],
+            None,
+            Duration::seconds(200),
+        )
+        .unwrap();
+        update_playlist_current_item(&mut db_conn, pid, Some(ids[4])).unwrap();
+        let order = |db_conn: &mut SqliteConnection| {
+            let playlist = query_playlist_from_id(db_conn, pid).unwrap();
+            query_playlist_items_in_order(db_conn, pid, playlist.first_playlist_item)
+                .unwrap()
+                .into_iter()
+                .map(|item| item.id)
+                .collect::<Vec<_>>()
+        };
+
+        shuffle_playlist_items(&mut db_conn, pid, true, &mut StdRng::seed_from_u64(42)).unwrap();
+        let shuffled = order(&mut db_conn);
+        assert_eq!(check_playlist(&mut db_conn, pid).unwrap(), vec![]);
+        assert_eq!(shuffled[..5], ids[..5]);
+        assert_ne!(shuffled, ids);
+        let mut sorted = shuffled.clone();
+        sorted.sort_by_key(|id| id.0);
+        assert_eq!(sorted, ids);
+
+        shuffle_playlist_items(&mut db_conn, pid, false, &mut StdRng::seed_from_u64(7)).unwrap();
+        assert_eq!(check_playlist(&mut db_conn, pid).unwrap(), vec![]);
+    }
+
+    #[tokio::test]
+    async fn move_items_to_arbitrary_positions() {
+        use crate::db::integrity::check_playlist;
+
+        let db = TestDb::new();
+        let mut db_conn = db.pool.get().unwrap();
+        let media = insert_test_media(&mut db_conn, 10);
+        let pid = create_empty_playlist(&mut db_conn, "test").await.unwrap();
+        let ids = append_to_playlist(
+            &mut db_conn,
+            pid,
+            None,
+            &[media.id; 8],
+            None,
+            Duration::seconds(80),
+        )
+        .unwrap();
+        let mut check = |selected: &[PlaylistItemId], target, expected: [usize; 8]| {
+            move_playlist_items(&mut db_conn, pid, selected, target).unwrap();
+            assert_eq!(check_playlist(&mut db_conn, pid).unwrap(), vec![]);
+            let playlist = query_playlist_from_id(&mut db_conn, pid).unwrap();
+            let order =
+                query_playlist_items_in_order(&mut db_conn, pid, playlist.first_playlist_item)
+                    .unwrap()
+                    .into_iter()
+                    .map(|item| item.id)
+                    .collect::<Vec<_>>();
+            assert_eq!(order, expected.map(|index| ids[index]));
+        };
+
+        check(
+            &[ids[5], ids[1], ids[2]],
+            MoveTarget::After(ids[6]),
+            [0, 3, 4, 6, 1, 2, 5, 7],
+        );
+        check(
+            &[ids[7], ids[0]],
+            MoveTarget::Start,
+            [0, 7, 3, 4, 6, 1, 2, 5],
+        );
+        check(&[ids[7], ids[3]], MoveTarget::End, [0, 4, 6, 1, 2, 5, 7, 3]);
+        // moving after a selected item keeps the items around it
+        check(
+            &[ids[4], ids[6], ids[2]],
+            MoveTarget::After(ids[6]),
+            [0, 4, 6, 2, 1, 5, 7, 3],
+        );
+
+        let other = create_empty_playlist(&mut db_conn, "other").await.unwrap();
+        let other_ids = append_to_playlist(
+            &mut db_conn,
+            other,
+            None,
+            &[media.id],
+            None,
+            Duration::seconds(10),
+        )
+        .unwrap();
+        assert!(matches!(
+            move_playlist_items(&mut db_conn, pid, &other_ids, MoveTarget::Start),
+            Err(ResourceQueryError::ResourceNotFound(_, _))
+        ));
+        assert!(matches!(
+            move_playlist_items(
+                &mut db_conn,
+                pid,
+                &[ids[0]],
+                MoveTarget::After(other_ids[0])
+            ),
+            Err(ResourceQueryError::ResourceNotFound(_, _))
+        ));
+    }
+}
@@ -0,0 +1,293 @@
+use diesel::{prelude::*, SqliteConnection};
+use rand::{seq::SliceRandom, Rng};
+
+use super::{
+    immediate_transaction,
+    playlist::{query_playlist_from_id, PlaylistId},
+    playlist_item::PlaylistItemId,
+    ResourceQueryError, ResourceQueryResult, ResourceType,
+};
+
+/// Play order of the items of a playlist, ordered by position, and how many
+/// of them were played in the current cycle.
+fn load_order(
+    db_conn: &mut SqliteConnection,
+    pid: PlaylistId,
+) -> ResourceQueryResult<(Vec<PlaylistItemId>, usize)> {
+    use crate::schema::shuffle_order::dsl::*;
+    let rows: Vec<(PlaylistItemId, bool)> = shuffle_order
+        .filter(playlist_id.eq(pid))
+        .order(position.asc())
+        .select((playlist_item_id, played))
+        .load(db_conn)?;
+    let played_count = rows.iter().take_while(|(_, is_played)| *is_played).count();
+    Ok((rows.into_iter().map(|(id, _)| id).collect(), played_count))
+}
+
+fn clear_order(db_conn: &mut SqliteConnection, pid: PlaylistId) -> ResourceQueryResult<()> {
+    use crate::schema::shuffle_order::dsl::*;
+    diesel::delete(shuffle_order.filter(playlist_id.eq(pid))).execute(db_conn)?;
+    Ok(())
+}
+
+/// Store the play order of a playlist, the first `played_count` items of
+/// which were played in the current cycle.
+fn store_order(
+    db_conn: &mut SqliteConnection,
+    pid: PlaylistId,
+    item_ids: &[PlaylistItemId],
+    played_count: usize,
+) -> ResourceQueryResult<()> {
+    use crate::schema::shuffle_order::dsl::*;
+    clear_order(db_conn, pid)?;
+    let rows = item_ids
+        .iter()
+        .enumerate()
+        .map(|(index, item_id)| {
+            (
+                playlist_id.eq(pid),
+                position.eq(index as i32),
+                playlist_item_id.eq(*item_id),
+                played.eq(index < played_count),
+            )
+        })
+        .collect::<Vec<_>>();
+    diesel::insert_into(shuffle_order)
+        .values(&rows)
+        .execute(db_conn)?;
+    Ok(())
+}
+
+fn query_item_ids(
+    db_conn: &mut SqliteConnection,
+    pid: PlaylistId,
+) -> ResourceQueryResult<Vec<PlaylistItemId>> {
+    use crate::schema::playlist_items::dsl::*;
+    Ok(playlist_items
+        .filter(playlist_id.eq(pid))
+        .order(id.asc())
+        .select(id)
+        .load(db_conn)?)
+}
+
+fn new_cycle(mut item_ids: Vec<PlaylistItemId>, rng: &mut impl Rng) -> Vec<PlaylistItemId> {
+    item_ids.shuffle(rng);
+    item_ids
+}
+
+pub fn set_playlist_shuffle(
+    db_conn: &mut SqliteConnection,
+    playlist_id: PlaylistId,
+    enabled: bool,
+) -> ResourceQueryResult<()> {
+    immediate_transaction(db_conn, |db_conn| {
+        use crate::schema::playlists::dsl::*;
+        let updated = diesel::update(playlists)
+            .filter(id.eq(playlist_id))
+            .set(shuffle.eq(enabled))
+            .execute(db_conn)?;
+        if updated == 0 {
+            return Err(ResourceQueryError::ResourceNotFound(
+                ResourceType::Playlist,
+                playlist_id.into(),
+            ));
+        }
+        // the order is generated again from the current item when needed
+        clear_order(db_conn, playlist_id)
+    })
+}
+
+/// Pick the item to play after the current one of a shuffled playlist.
+///
+/// Every item is played once per cycle, in a random order that is stored so
+/// that [`prev_shuffled_item`] can retrace it. Items added during a cycle are
+/// inserted at random among the items yet to be played, and an item jumped
+/// to from outside of the play order counts as played from then on. Once a
+/// cycle is over, a new one starts if `wrap` is set, otherwise there is no
+/// next item.
+pub fn next_shuffled_item(
+    db_conn: &mut SqliteConnection,
+    playlist_id: PlaylistId,
+    wrap: bool,
+    rng: &mut impl Rng,
+) -> ResourceQueryResult<Option<PlaylistItemId>> {
+    immediate_transaction(db_conn, |db_conn| {
+        let playlist = query_playlist_from_id(db_conn, playlist_id)?;
+        let item_ids = query_item_ids(db_conn, playlist_id)?;
+        let (mut order, mut played) = load_order(db_conn, playlist_id)?;
+        if order.is_empty() {
+            // shuffle was just enabled
+            order = new_cycle(item_ids.clone(), rng);
+        } else {
+            let new_ids = item_ids
+                .iter()
+                .filter(|id| !order.contains(id))
+                .copied()
+                .collect::<Vec<_>>();
+            for id in new_ids {
+                let index = rng.gen_range(played..=order.len());
+                order.insert(index, id);
+            }
+        }
+        // the current item was not picked from the play order, e.g. it was
+        // jumped to, so it moves right after the items already played
+        let current = playlist
+            .current_item
+            .and_then(|current| order[played..].iter().position(|id| *id == current));
+        if let Some(index) = current {
+            order[played..=played + index].rotate_right(1);
+            played += 1;
+        }
+
+        let next = if let Some(next) = order.get(played) {
+            Some(*next)
+        } else if wrap && !item_ids.is_empty() {
+            order = new_cycle(item_ids, rng);
+            // avoid playing the same item twice in a row
+            if order.len() > 1 && Some(order[0]) == playlist.current_item {
+                order.swap(0, 1);
+            }
+            played = 0;
+            order.first().copied()
+        } else {
+            None
+        };
+        if next.is_some() {
+            played += 1;
+        }
+        store_order(db_conn, playlist_id, &order, played)?;
+        Ok(next)
+    })
+}
+
+/// The item played before the current one of a shuffled playlist, if any.
+pub fn prev_shuffled_item(
+    db_conn: &mut SqliteConnection,
+    playlist_id: PlaylistId,
+) -> ResourceQueryResult<Option<PlaylistItemId>> {
+    let playlist = query_playlist_from_id(db_conn, playlist_id)?;
+    let (order, played) = load_order(db_conn, playlist_id)?;
+    Ok(playlist.current_item.and_then(|current| {
+        // an item jumped to is played right after the last played one
+        let index = order[..played]
+            .iter()
+            .position(|id| *id == current)
+            .unwrap_or(played);
+        index.checked_sub(1).map(|index| order[index])
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::{
+        integrity::check_playlist,
+        playlist::{append_to_playlist, create_empty_playlist, update_playlist_current_item},
+        playlist_item::{query_playlist_items_in_order, remove_playlist_item},
+        test_utils::{insert_test_media, playlist_with_medias, TestDb},
+    };
+    use rand::{rngs::StdRng, SeedableRng};
+    use std::collections::HashSet;
+    use time::Duration;
+
+    #[tokio::test]
+    async fn shuffle_plays_every_item_once_per_cycle() {
+        let db = TestDb::new();
+        let mut db_conn = db.pool.get().unwrap();
+        let mut rng = StdRng::seed_from_u64(3);
+        let media = insert_test_media(&mut db_conn, 10);
+        let pid = create_empty_playlist(&mut db_conn, "test").await.unwrap();
+        let ids = append_to_playlist(
+            &mut db_conn,
+            pid,
+            None,
+            &[media.id; 10],
+            None,
+            Duration::seconds(100),
+        )
+        .unwrap();
+        update_playlist_current_item(&mut db_conn, pid, Some(ids[0])).unwrap();
+        set_playlist_shuffle(&mut db_conn, pid, true).unwrap();
+        remove_playlist_item(&mut db_conn, ids[5]).unwrap();
+
+        let mut played = vec![ids[0]];
+        while let Some(next) = next_shuffled_item(&mut db_conn, pid, false, &mut rng).unwrap() {
+            update_playlist_current_item(&mut db_conn, pid, Some(next)).unwrap();
+            played.push(next);
+            if played.len() == 3 {
+                // added mid-cycle, still played in this cycle
+                let added = append_to_playlist(
+                    &mut db_conn,
+                    pid,
+                    None,
+                    &[media.id],
+                    None,
+                    Duration::seconds(10),
+                )
+                .unwrap();
+                assert!(!played.contains(&added[0]));
+            }
+        }
+        assert_eq!(played.len(), 10);
+        assert_eq!(played.iter().collect::<HashSet<_>>().len(), 10);
+        assert!(!played.contains(&ids[5]));
+
+        // prev retraces the play order
+        assert_eq!(
+            prev_shuffled_item(&mut db_conn, pid).unwrap(),
+            Some(played[8])
+        );
+
+        // the stored order of the playlist is left alone
+        assert_eq!(check_playlist(&mut db_conn, pid).unwrap(), vec![]);
+        let playlist = query_playlist_from_id(&mut db_conn, pid).unwrap();
+        let order = query_playlist_items_in_order(&mut db_conn, pid, playlist.first_playlist_item)
+            .unwrap()
+            .into_iter()
+            .map(|item| item.id)
+            .collect::<Vec<_>>();
+        assert_eq!(order[1..6], ids[..5]);
+        assert_eq!(order[6..], ids[6..]);
+
+        // a new cycle starts when wrapping around
+        let next = next_shuffled_item(&mut db_conn, pid, true, &mut rng).unwrap();
+        assert!(next.is_some_and(|next| Some(next) != playlist.current_item));
+        assert_eq!(load_order(&mut db_conn, pid).unwrap().0.len(), 10);
+    }
+
+    #[tokio::test]
+    async fn jumping_to_an_item_keeps_the_cycle() {
+        let db = TestDb::new();
+        let mut db_conn = db.pool.get().unwrap();
+        let mut rng = StdRng::seed_from_u64(5);
+        let (playlist, ids) = playlist_with_medias(&mut db_conn, &[10; 8]).await;
+        let pid = playlist.id;
+        update_playlist_current_item(&mut db_conn, pid, Some(ids[0])).unwrap();
+        set_playlist_shuffle(&mut db_conn, pid, true).unwrap();
+
+        let mut played = vec![ids[0]];
+        while let Some(next) = next_shuffled_item(&mut db_conn, pid, false, &mut rng).unwrap() {
+            assert!(!played.contains(&next));
+            update_playlist_current_item(&mut db_conn, pid, Some(next)).unwrap();
+            played.push(next);
+            if played.len() == 3 {
+                // jump past the other items yet to be played, as
+                // /playlist/goto does
+                let (order, _) = load_order(&mut db_conn, pid).unwrap();
+                let jumped = *order.last().unwrap();
+                assert!(!played.contains(&jumped));
+                update_playlist_current_item(&mut db_conn, pid, Some(jumped)).unwrap();
+                assert_eq!(
+                    prev_shuffled_item(&mut db_conn, pid).unwrap(),
+                    Some(played[2])
+                );
+                played.push(jumped);
+            } else if played.len() == 6 {
+                // jumping back does not play the following items again
+                update_playlist_current_item(&mut db_conn, pid, Some(played[1])).unwrap();
+            }
+        }
+        assert_eq!(played.len(), 8);
+        assert_eq!(played.iter().collect::<HashSet<_>>().len(), 8);
+    }
+}
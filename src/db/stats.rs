@@ -0,0 +1,342 @@
+use diesel::{
+    prelude::*,
+    sql_types::{BigInt, Integer, Nullable, Text},
+    SqliteConnection,
+};
+use serde::{Deserialize, Serialize};
+
+use super::{media::Media, playlist::PlaylistId};
+
+/// Length of the buckets listening time is grouped into.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum StatsPeriod {
+    #[default]
+    Day,
+    Week,
+}
+
+impl StatsPeriod {
+    pub const ALL: [Self; 2] = [Self::Day, Self::Week];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Day => "day",
+            Self::Week => "week",
+        }
+    }
+
+    fn strftime_format(&self) -> &'static str {
+        match self {
+            Self::Day => "%Y-%m-%d",
+            Self::Week => "%Y-W%W",
+        }
+    }
+}
+
+/// Seconds listened to a history entry: the time between its start and its
+/// end (or now, if it is still playing), but no more than the media lasts.
+const LISTENED_SECONDS: &str = "MIN(\
+    CAST(strftime('%s', COALESCE(play_history.ended_at, CURRENT_TIMESTAMP)) AS INTEGER) \
+        - CAST(strftime('%s', play_history.started_at) AS INTEGER), \
+    COALESCE(medias.duration, 9223372036854775807))";
+
+#[derive(QueryableByName, Serialize, Debug)]
+pub struct LibraryTotals {
+    #[diesel(sql_type = BigInt)]
+    pub medias: i64,
+    #[diesel(sql_type = BigInt)]
+    pub views: i64,
+    /// Sum of the durations of every media, in seconds.
+    #[diesel(sql_type = BigInt)]
+    pub duration: i64,
+    #[diesel(sql_type = BigInt)]
+    pub plays: i64,
+    /// Total listening time, in seconds.
+    #[diesel(sql_type = BigInt)]
+    pub listened: i64,
+}
+
+/// Artists, as given by `Media::display_artist`.
+#[derive(QueryableByName, Serialize, Debug, PartialEq, Eq)]
+pub struct ArtistStats {
+    #[diesel(sql_type = Text)]
+    pub artist: String,
+    #[diesel(sql_type = BigInt)]
+    pub medias: i64,
+    #[diesel(sql_type = BigInt)]
+    pub views: i64,
+}
+
+#[derive(QueryableByName, Serialize, Debug, PartialEq, Eq)]
+pub struct ListeningTime {
+    /// `YYYY-MM-DD` for days, `YYYY-Www` for weeks.
+    #[diesel(sql_type = Text)]
+    pub period: String,
+    #[diesel(sql_type = BigInt)]
+    pub plays: i64,
+    /// In seconds.
+    #[diesel(sql_type = BigInt)]
+    pub listened: i64,
+}
+
+/// Plays of a playlist. Plays of deleted playlists are grouped together,
+/// without an ID nor a title.
+#[derive(QueryableByName, Serialize, Debug, PartialEq, Eq)]
+pub struct PlaylistPlays {
+    #[diesel(sql_type = Nullable<Integer>)]
+    pub playlist_id: Option<PlaylistId>,
+    #[diesel(sql_type = Nullable<Text>)]
+    pub title: Option<String>,
+    #[diesel(sql_type = BigInt)]
+    pub plays: i64,
+    #[diesel(sql_type = BigInt)]
+    pub completed: i64,
+    /// In seconds.
+    #[diesel(sql_type = BigInt)]
+    pub listened: i64,
+}
+
+#[derive(Serialize, Debug)]
+pub struct Stats {
+    pub totals: LibraryTotals,
+    pub top_medias: Vec<Media>,
+    pub top_artists: Vec<ArtistStats>,
+    /// Most recent periods first.
+    pub listening_time: Vec<ListeningTime>,
+    pub playlists: Vec<PlaylistPlays>,
+}
+
+pub fn query_library_totals(
+    db_conn: &mut SqliteConnection,
+) -> Result<LibraryTotals, diesel::result::Error> {
+    diesel::sql_query(format!(
+        "SELECT \
+            (SELECT COUNT(*) FROM medias) AS medias, \
+            (SELECT COALESCE(SUM(views), 0) FROM medias) AS views, \
+            (SELECT COALESCE(SUM(duration), 0) FROM medias) AS duration, \
+            (SELECT COUNT(*) FROM play_history) AS plays, \
+            (SELECT COALESCE(SUM({LISTENED_SECONDS}), 0) \
+                FROM play_history JOIN medias ON medias.id = play_history.media_id) AS listened"
+    ))
+    .get_result(db_conn)
+}
+
+/// Most viewed medias first.
+pub fn query_top_medias(
+    db_conn: &mut SqliteConnection,
+    limit: i64,
+) -> Result<Vec<Media>, diesel::result::Error> {
+    use crate::schema::medias::dsl::*;
+    medias
+        .filter(views.gt(0))
+        .order((views.desc(), id.asc()))
+        .limit(limit)
+        .select(Media::as_select())
+        .load(db_conn)
+}
+
+/// Most viewed artists first.
+pub fn query_top_artists(
+    db_conn: &mut SqliteConnection,
+    limit: i64,
+) -> Result<Vec<ArtistStats>, diesel::result::Error> {
+    diesel::sql_query(
+        "SELECT COALESCE(alt_artist, artist) AS artist, \
+            COUNT(*) AS medias, SUM(views) AS views \
+         FROM medias \
+         GROUP BY 1 \
+         HAVING SUM(views) > 0 \
+         ORDER BY views DESC, artist ASC \
+         LIMIT ?",
+    )
+    .bind::<BigInt, _>(limit)
+    .load(db_conn)
+}
+
+/// Listening time of the `limit` most recent periods with any plays.
+pub fn query_listening_time(
+    db_conn: &mut SqliteConnection,
+    period: StatsPeriod,
+    limit: i64,
+) -> Result<Vec<ListeningTime>, diesel::result::Error> {
+    diesel::sql_query(format!(
+        "SELECT strftime(?, play_history.started_at) AS period, \
+            COUNT(*) AS plays, \
+            SUM({LISTENED_SECONDS}) AS listened \
+         FROM play_history JOIN medias ON medias.id = play_history.media_id \
+         GROUP BY 1 \
+         ORDER BY 1 DESC \
+         LIMIT ?"
+    ))
+    .bind::<Text, _>(period.strftime_format())
+    .bind::<BigInt, _>(limit)
+    .load(db_conn)
+}
+
+/// Most played playlists first.
+pub fn query_playlist_plays(
+    db_conn: &mut SqliteConnection,
+    limit: i64,
+) -> Result<Vec<PlaylistPlays>, diesel::result::Error> {
+    diesel::sql_query(format!(
+        "SELECT play_history.playlist_id AS playlist_id, playlists.title AS title, \
+            COUNT(*) AS plays, \
+            SUM(play_history.completed) AS completed, \
+            SUM({LISTENED_SECONDS}) AS listened \
+         FROM play_history \
+         JOIN medias ON medias.id = play_history.media_id \
+         LEFT JOIN playlists ON playlists.id = play_history.playlist_id \
+         GROUP BY play_history.playlist_id \
+         ORDER BY plays DESC, play_history.playlist_id ASC \
+         LIMIT ?"
+    ))
+    .bind::<BigInt, _>(limit)
+    .load(db_conn)
+}
+
+pub fn query_stats(
+    db_conn: &mut SqliteConnection,
+    period: StatsPeriod,
+    limit: i64,
+) -> Result<Stats, diesel::result::Error> {
+    Ok(Stats {
+        totals: query_library_totals(db_conn)?,
+        top_medias: query_top_medias(db_conn, limit)?,
+        top_artists: query_top_artists(db_conn, limit)?,
+        listening_time: query_listening_time(db_conn, period, limit)?,
+        playlists: query_playlist_plays(db_conn, limit)?,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::{
+        media::increase_media_view_count,
+        playlist::{append_to_playlist, create_empty_playlist},
+        test_utils::{insert_test_media, TestDb},
+    };
+    use time::Duration;
+
+    #[tokio::test]
+    async fn stats_aggregate_views_and_history() {
+        let db = TestDb::new();
+        let mut db_conn = db.pool.get().unwrap();
+        let short = insert_test_media(&mut db_conn, 60);
+        let long = insert_test_media(&mut db_conn, 600);
+        {
+            use crate::schema::medias::dsl::*;
+            diesel::update(medias.filter(id.eq(long.id)))
+                .set(alt_artist.eq("someone else"))
+                .execute(&mut db_conn)
+                .unwrap();
+        }
+        for media in [short.id, long.id, long.id] {
+            increase_media_view_count(&mut db_conn, media).unwrap();
+        }
+        let playlist_id = create_empty_playlist(&mut db_conn, "test").await.unwrap();
+        let item_ids = append_to_playlist(
+            &mut db_conn,
+            playlist_id,
+            None,
+            &[short.id, long.id],
+            None,
+            Duration::seconds(660),
+        )
+        .unwrap();
+
+        // played for longer than the media lasts, e.g. while paused
+        for (media_id, item_id, started_at, ended_at, completed) in [
+            (
+                short.id,
+                item_ids[0],
+                "2024-03-04 10:00:00",
+                "2024-03-04 11:00:00",
+                true,
+            ),
+            (
+                long.id,
+                item_ids[1],
+                "2024-03-05 10:00:00",
+                "2024-03-05 10:01:40",
+                false,
+            ),
+            (
+                long.id,
+                item_ids[1],
+                "2024-03-05 12:00:00",
+                "2024-03-05 12:10:00",
+                true,
+            ),
+        ] {
+            diesel::sql_query(
+                "INSERT INTO play_history \
+                 (media_id, playlist_id, playlist_item_id, started_at, ended_at, completed) \
+                 VALUES (?, ?, ?, ?, ?, ?)",
+            )
+            .bind::<Integer, _>(media_id)
+            .bind::<Integer, _>(playlist_id)
+            .bind::<Integer, _>(item_id)
+            .bind::<Text, _>(started_at)
+            .bind::<Text, _>(ended_at)
+            .bind::<diesel::sql_types::Bool, _>(completed)
+            .execute(&mut db_conn)
+            .unwrap();
+        }
+
+        let stats = query_stats(&mut db_conn, StatsPeriod::Day, 10).unwrap();
+        assert_eq!(stats.totals.medias, 2);
+        assert_eq!(stats.totals.views, 3);
+        assert_eq!(stats.totals.plays, 3);
+        assert_eq!(stats.totals.listened, 760);
+        assert_eq!(
+            stats.top_medias.iter().map(|m| m.id).collect::<Vec<_>>(),
+            [long.id, short.id]
+        );
+        assert_eq!(
+            stats.top_artists,
+            [
+                ArtistStats {
+                    artist: "someone else".into(),
+                    medias: 1,
+                    views: 2,
+                },
+                ArtistStats {
+                    artist: "artist".into(),
+                    medias: 1,
+                    views: 1,
+                },
+            ]
+        );
+        assert_eq!(
+            stats.listening_time,
+            [
+                ListeningTime {
+                    period: "2024-03-05".into(),
+                    plays: 2,
+                    listened: 700,
+                },
+                ListeningTime {
+                    period: "2024-03-04".into(),
+                    plays: 1,
+                    listened: 60,
+                },
+            ]
+        );
+        assert_eq!(
+            stats.playlists,
+            [PlaylistPlays {
+                playlist_id: Some(playlist_id),
+                title: Some("test".into()),
+                plays: 3,
+                completed: 2,
+                listened: 760,
+            }]
+        );
+
+        let weeks = query_listening_time(&mut db_conn, StatsPeriod::Week, 10).unwrap();
+        assert_eq!(weeks.len(), 1);
+        assert_eq!(weeks[0].listened, 760);
+    }
+}
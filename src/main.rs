@@ -2,6 +2,11 @@ use std::borrow::Cow;
 
 use anyhow::{Context, Result};
 use context::create_app_router;
+use db::{
+    establish_connection,
+    integrity::{fsck_all_playlists, fsck_playlist},
+    playlist::PlaylistId,
+};
 
 use dotenvy::dotenv;
 
@@ -21,6 +26,13 @@ async fn main() -> Result<()> {
         .init();
     dotenv().context("unable to load .env")?;
 
+    let args = std::env::args().skip(1).collect::<Vec<_>>();
+    match args.first().map(String::as_str) {
+        Some("fsck") => return fsck(&args[1..]),
+        Some(arg) => anyhow::bail!("unknown subcommand: {arg}"),
+        None => {}
+    }
+
     let app = create_app_router()
         .await
         .context("unable to create app router")?;
@@ -35,3 +47,41 @@ async fn main() -> Result<()> {
         .context("unable to serve axum server")?;
     Ok(())
 }
+
+/// `plst3 fsck [--repair] [PLAYLIST_ID...]`: check the given playlists, or
+/// every playlist if none is given. Exits with status 1 if issues were found
+/// and left unrepaired.
+fn fsck(args: &[String]) -> Result<()> {
+    let repair = args.iter().any(|arg| arg == "--repair");
+    let playlist_ids = args
+        .iter()
+        .filter(|arg| *arg != "--repair")
+        .map(|arg| arg.parse::<PlaylistId>())
+        .collect::<Result<Vec<_>, _>>()
+        .context("invalid playlist id")?;
+    let mut db_conn = establish_connection()?.get()?;
+    let reports = if playlist_ids.is_empty() {
+        fsck_all_playlists(&mut db_conn, repair)?
+    } else {
+        playlist_ids
+            .into_iter()
+            .map(|id| fsck_playlist(&mut db_conn, id, repair))
+            .collect::<Result<_, _>>()?
+    };
+
+    let mut num_issues = 0;
+    for report in reports {
+        for issue in report.issues.iter() {
+            println!("playlist {}: {issue}", report.playlist_id);
+        }
+        if report.repaired {
+            println!("playlist {}: repaired", report.playlist_id);
+        }
+        num_issues += report.issues.len();
+    }
+    println!("{num_issues} issues found");
+    if num_issues > 0 && !repair {
+        std::process::exit(1);
+    }
+    Ok(())
+}
@@ -0,0 +1,241 @@
+use std::{borrow::Cow, time::Duration};
+
+use anyhow::Result;
+use async_trait::async_trait;
+use lazy_static::lazy_static;
+use percent_encoding::percent_decode_str;
+use reqwest::{
+    header::{CONTENT_TYPE, RANGE},
+    Client, Response, StatusCode,
+};
+use url::Url;
+
+use crate::db::media::NewMedia;
+
+use super::{local::probe_media, youtube::is_youtube_url, MediaResolveError, MediaResolver};
+
+lazy_static! {
+    static ref CLIENT: Client = Client::builder()
+        .user_agent(concat!("plst3/", env!("CARGO_PKG_VERSION")))
+        .timeout(Duration::from_secs(10))
+        .build()
+        .expect("unable to build http client");
+}
+
+fn is_media_content_type(content_type: &str) -> bool {
+    let mime = content_type
+        .split(';')
+        .next()
+        .unwrap_or_default()
+        .trim()
+        .to_ascii_lowercase();
+    mime.starts_with("audio/") || mime.starts_with("video/") || mime == "application/ogg"
+}
+
+fn content_type(response: &Response) -> Option<&str> {
+    response
+        .headers()
+        .get(CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+}
+
+/// Send a HEAD request to `url`, falling back to a GET request of the first
+/// byte for servers that do not implement HEAD properly.
+async fn probe_url(url: &Url) -> Result<Response, MediaResolveError> {
+    // slow or unreachable sites may still be handled by the yt-dlp resolver
+    let map_error = |e: reqwest::Error| {
+        tracing::warn!("probing {url} failed: {e}");
+        MediaResolveError::InvalidMedia
+    };
+    match CLIENT.head(url.clone()).send().await {
+        Ok(response) if response.status().is_success() && content_type(&response).is_some() => {
+            return Ok(response)
+        }
+        Ok(_) => {}
+        Err(e) => tracing::debug!("HEAD request to {url} failed: {e}"),
+    }
+
+    CLIENT
+        .get(url.clone())
+        .header(RANGE, "bytes=0-0")
+        .send()
+        .await
+        .map_err(map_error)
+}
+
+fn file_name(url: &Url) -> Option<String> {
+    url.path_segments()?
+        .rfind(|segment| !segment.is_empty())
+        .map(|segment| percent_decode_str(segment).decode_utf8_lossy().into_owned())
+}
+
+pub async fn resolve_media(url: &Url) -> Result<NewMedia<'static>, MediaResolveError> {
+    if (url.scheme() != "http" && url.scheme() != "https") || is_youtube_url(url) {
+        return Err(MediaResolveError::UnsupportedUrl);
+    }
+
+    let response = probe_url(url).await?;
+    match response.status() {
+        StatusCode::NOT_FOUND | StatusCode::GONE => return Err(MediaResolveError::MediaNotFound),
+        status if !status.is_success() => {
+            tracing::warn!("probing {url} returned status {status}");
+            return Err(MediaResolveError::InvalidMedia);
+        }
+        _ => {}
+    }
+    // webpages are not medias by themselves, but the registry still tries
+    // the yt-dlp resolver on them
+    if !content_type(&response).is_some_and(is_media_content_type) {
+        return Err(MediaResolveError::InvalidMedia);
+    }
+
+    let probe = probe_media(url.as_str()).await?;
+    let title: Cow<'static, str> = match probe.tag("title") {
+        Some(title) => title.to_owned().into(),
+        None => file_name(url)
+            .map(Cow::Owned)
+            .unwrap_or_else(|| "<invalid basename>".into()),
+    };
+    let artist: Cow<'static, str> = probe
+        .tag("artist")
+        .or_else(|| probe.tag("album_artist"))
+        .map(|artist| artist.to_owned().into())
+        .or_else(|| url.host_str().map(|host| host.to_owned().into()))
+        .unwrap_or_else(|| "<http media>".into());
+    Ok(NewMedia {
+        title,
+        artist,
+        duration: probe.duration,
+        url: url.to_string().into(),
+        media_type: "http".into(),
+        album: probe.tag("album").map(|album| album.to_owned().into()),
+        track: probe.track(),
+        release_date: probe.tag("date").map(|date| date.to_owned().into()),
+        extractor: None,
+    })
+}
+
+/// Direct links to audio or video files, played as-is by the browser.
+pub struct HttpResolver;
+
+#[async_trait]
+impl MediaResolver for HttpResolver {
+    fn name(&self) -> &'static str {
+        "http"
+    }
+
+    fn media_type(&self) -> &'static str {
+        "http"
+    }
+
+    async fn resolve_media(&self, url: &Url) -> Result<NewMedia<'static>, MediaResolveError> {
+        resolve_media(url).await
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use crate::resolvers::test_utils::FfprobeStub;
+    // axum and reqwest depend on different versions of `http`
+    use axum::{
+        http::{
+            header::{CONTENT_TYPE, RANGE},
+            HeaderMap, StatusCode,
+        },
+        routing::get,
+        Router,
+    };
+    use tokio::net::TcpListener;
+
+    /// Serve a few fake files on a random local port.
+    async fn serve() -> Url {
+        let app = Router::new()
+            .route(
+                "/song.mp3",
+                get(|| async { ([(CONTENT_TYPE, "audio/mpeg")], "") }),
+            )
+            .route(
+                "/page",
+                get(|| async { ([(CONTENT_TYPE, "text/html")], "<html>") }),
+            )
+            .route(
+                "/no-head.ogg",
+                get(|headers: HeaderMap| async move {
+                    // only answers ranged requests
+                    if headers.get(RANGE).is_some_and(|range| range == "bytes=0-0") {
+                        (
+                            StatusCode::PARTIAL_CONTENT,
+                            [(CONTENT_TYPE, "audio/ogg")],
+                            "O",
+                        )
+                    } else {
+                        (StatusCode::BAD_REQUEST, [(CONTENT_TYPE, "text/plain")], "")
+                    }
+                })
+                .head(|| async { StatusCode::METHOD_NOT_ALLOWED }),
+            );
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+        Url::parse(&format!("http://{addr}/")).unwrap()
+    }
+
+    #[tokio::test]
+    async fn media_content_types_are_accepted() {
+        let base = serve().await;
+        let _stub = FfprobeStub::install(r#"echo '{"format": {"duration": "12.4"}}'"#).await;
+        let url = base.join("song.mp3").unwrap();
+        let media = resolve_media(&url).await.unwrap();
+        assert_eq!(media.title, "song.mp3");
+        assert_eq!(media.artist, "127.0.0.1");
+        assert_eq!(media.duration, Some(12));
+        assert_eq!(media.media_type, "http");
+        assert_eq!(media.url, url.as_str());
+    }
+
+    #[tokio::test]
+    async fn ranged_get_is_used_without_head() {
+        let base = serve().await;
+        let _stub = FfprobeStub::install(r#"echo '{"format": {}}'"#).await;
+        let media = resolve_media(&base.join("no-head.ogg").unwrap())
+            .await
+            .unwrap();
+        assert_eq!(media.title, "no-head.ogg");
+        assert_eq!(media.duration, None);
+    }
+
+    #[tokio::test]
+    async fn webpages_are_invalid_media() {
+        let base = serve().await;
+        assert!(matches!(
+            resolve_media(&base.join("page").unwrap()).await,
+            Err(MediaResolveError::InvalidMedia)
+        ));
+    }
+
+    #[tokio::test]
+    async fn dropped_connections_are_invalid_media() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                drop(stream);
+            }
+        });
+        let url = Url::parse(&format!("http://{addr}/song.mp3")).unwrap();
+        assert!(matches!(
+            resolve_media(&url).await,
+            Err(MediaResolveError::InvalidMedia)
+        ));
+    }
+
+    #[tokio::test]
+    async fn missing_files_are_not_found() {
+        let base = serve().await;
+        assert!(matches!(
+            resolve_media(&base.join("missing.mp3").unwrap()).await,
+            Err(MediaResolveError::MediaNotFound)
+        ));
+    }
+}
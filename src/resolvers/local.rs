@@ -1,7 +1,19 @@
-use super::MediaResolveError;
+use super::{playlist_file::is_playlist_file, MediaListEntry, MediaResolveError, MediaResolver};
 use crate::db::media::{NewMedia, NewMediaList};
 use anyhow::{anyhow, Context, Result};
-use std::{borrow::Cow, ffi::OsStr, io::ErrorKind, path::Path, sync::Once};
+use async_trait::async_trait;
+use serde::Deserialize;
+use std::{
+    borrow::Cow,
+    cmp::Ordering,
+    collections::HashMap,
+    ffi::OsStr,
+    io::ErrorKind,
+    iter::Peekable,
+    path::{Path, PathBuf},
+    str::Chars,
+    sync::Once,
+};
 use tokio::{fs::canonicalize, process::Command};
 use url::Url;
 
@@ -15,17 +27,174 @@ async fn url_from_file_path(path: impl AsRef<Path>) -> Result<String> {
     .to_string())
 }
 
-async fn url_from_dir_path(path: impl AsRef<Path>) -> Result<String> {
-    Ok(Url::from_directory_path(
+async fn url_from_dir_path(path: impl AsRef<Path>, recursive: bool) -> Result<String> {
+    let mut url = Url::from_directory_path(
         canonicalize(path)
             .await
             .context("unable to canonicalize path")?,
     )
-    .map_err(|_| anyhow!("unable to construct url from directory path"))?
-    .to_string())
+    .map_err(|_| anyhow!("unable to construct url from directory path"))?;
+    if recursive {
+        url.set_query(Some("recursive"));
+    }
+    Ok(url.to_string())
+}
+
+/// Directory urls with a `recursive` query parameter (e.g.
+/// `file:///music/?recursive`) also include medias in subdirectories.
+fn is_recursive(url: &Url) -> bool {
+    url.query_pairs()
+        .any(|(key, value)| key == "recursive" && value != "false")
+}
+
+const PLAYABLE_EXTENSIONS: &[&str] = &[
+    "aac", "aif", "aiff", "alac", "ape", "avi", "flac", "m4a", "m4v", "mka", "mkv", "mov", "mp3",
+    "mp4", "mpc", "oga", "ogg", "ogv", "opus", "wav", "webm", "wma", "wmv", "wv",
+];
+
+pub(super) fn is_playable(path: &Path) -> bool {
+    path.extension()
+        .and_then(OsStr::to_str)
+        .map(|ext| PLAYABLE_EXTENSIONS.contains(&ext.to_ascii_lowercase().as_str()))
+        .unwrap_or_default()
+}
+
+fn take_number(chars: &mut Peekable<Chars>) -> String {
+    let mut number = String::new();
+    while let Some(c) = chars.next_if(char::is_ascii_digit) {
+        number.push(c);
+    }
+    number
+}
+
+/// Compare file names such that `2.mp3` comes before `10.mp3`.
+fn natural_cmp(a: &str, b: &str) -> Ordering {
+    let mut x = a.chars().peekable();
+    let mut y = b.chars().peekable();
+    loop {
+        let ordering = match (x.peek(), y.peek()) {
+            (None, None) => return a.cmp(b),
+            (None, Some(_)) => Ordering::Less,
+            (Some(_), None) => Ordering::Greater,
+            (Some(c), Some(d)) if c.is_ascii_digit() && d.is_ascii_digit() => {
+                let m = take_number(&mut x);
+                let n = take_number(&mut y);
+                let m = m.trim_start_matches('0');
+                let n = n.trim_start_matches('0');
+                m.len().cmp(&n.len()).then_with(|| m.cmp(n))
+            }
+            (Some(c), Some(d)) => {
+                let ordering = c.to_lowercase().cmp(d.to_lowercase());
+                x.next();
+                y.next();
+                ordering
+            }
+        };
+        if ordering != Ordering::Equal {
+            return ordering;
+        }
+    }
+}
+
+/// List the playable files of `dir` in natural order. Subdirectories are
+/// walked in place when `recursive` is set.
+fn list_playable_files(dir: &Path, recursive: bool, files: &mut Vec<PathBuf>) -> Result<()> {
+    let mut entries = std::fs::read_dir(dir)
+        .with_context(|| format!("unable to read directory {}", dir.display()))?
+        .map(|entry| entry.map(|entry| entry.path()))
+        .collect::<Result<Vec<_>, _>>()
+        .with_context(|| format!("unable to read directory {}", dir.display()))?;
+    entries.sort_by(|a, b| {
+        natural_cmp(
+            &a.file_name().unwrap_or_default().to_string_lossy(),
+            &b.file_name().unwrap_or_default().to_string_lossy(),
+        )
+    });
+    for path in entries {
+        if path.is_dir() {
+            if recursive {
+                list_playable_files(&path, recursive, files)?;
+            }
+        } else if is_playable(&path) {
+            files.push(path);
+        }
+    }
+    Ok(())
+}
+
+#[derive(Deserialize, Default)]
+struct FfprobeOutput {
+    #[serde(default)]
+    format: FfprobeSection,
+    #[serde(default)]
+    streams: Vec<FfprobeSection>,
+}
+
+#[derive(Deserialize, Default)]
+struct FfprobeSection {
+    duration: Option<String>,
+    #[serde(default)]
+    tags: HashMap<String, String>,
+}
+
+/// Duration and tags of a media, as reported by ffprobe.
+pub struct MediaProbe {
+    pub duration: Option<i32>,
+    tags: HashMap<String, String>,
+}
+
+impl MediaProbe {
+    /// Look up a tag by its case-insensitive name.
+    pub fn tag(&self, key: &str) -> Option<&str> {
+        self.tags
+            .get(key)
+            .map(String::as_str)
+            .map(str::trim)
+            .filter(|value| !value.is_empty())
+    }
+
+    /// The track number, from tags of the form `3` or `3/12`.
+    pub fn track(&self) -> Option<i32> {
+        self.tag("track")
+            .and_then(|track| track.split('/').next())
+            .and_then(|track| track.trim().parse().ok())
+    }
+}
+
+impl From<FfprobeOutput> for MediaProbe {
+    fn from(output: FfprobeOutput) -> Self {
+        let duration = output
+            .format
+            .duration
+            .as_deref()
+            .and_then(|duration| {
+                duration
+                    .trim()
+                    .parse::<f64>()
+                    .map_err(|e| {
+                        tracing::warn!("error interpreting duration returned from ffprobe: {e}")
+                    })
+                    .ok()
+            })
+            .map(|secs| secs.round() as i32);
+        // container tags take priority, but some formats (e.g. ogg) store
+        // their metadata on the streams instead
+        let mut tags = HashMap::new();
+        for section in std::iter::once(output.format).chain(output.streams) {
+            for (key, value) in section.tags {
+                tags.entry(key.to_lowercase()).or_insert(value);
+            }
+        }
+        Self { duration, tags }
+    }
 }
 
-async fn get_media_duration(path: &Path) -> Result<Option<i32>> {
+/// Run ffprobe on `input`.
+///
+/// Inputs that ffprobe can not read are reported as
+/// [`MediaResolveError::InvalidMedia`], while a missing duration is not an
+/// error, since some formats simply do not store one.
+pub async fn probe_media(input: impl AsRef<OsStr>) -> Result<MediaProbe, MediaResolveError> {
     static FFPROBE_ENV: Once = Once::new();
     let executable: Cow<'static, OsStr> = std::env::var_os("FFPROBE_EXECUTABLE")
         .map(Cow::Owned)
@@ -37,29 +206,46 @@ async fn get_media_duration(path: &Path) -> Result<Option<i32>> {
             });
             OsStr::new("ffprobe").into()
         });
-    let output = Command::new(&executable)
+    let output = match Command::new(&executable)
         .args([
             "-v",
             "error",
             "-show_entries",
-            "format=duration",
+            "format=duration:format_tags:stream_tags",
             "-of",
-            "default=noprint_wrappers=1:nokey=1",
+            "json",
         ])
-        .arg(path)
+        .arg(input)
         .output()
         .await
-        .context("unable to execute ffprobe process")?;
-    if output.status.success() {
-        tracing::info!("ffprobe succeeded");
-        return Ok(std::str::from_utf8(&output.stdout)
-            .context("unable to convert duration to utf8")
-            .and_then(|s| Ok(s.trim().parse::<f64>()?))
-            .map_err(|e| tracing::warn!("error interpreting duration returned from ffprobe: {e}"))
-            .map(|secs| secs.round() as i32)
-            .ok());
+    {
+        Ok(output) => output,
+        Err(e) if e.kind() == ErrorKind::NotFound => {
+            return Err(MediaResolveError::FailedProcessing(anyhow!(
+                "ffprobe executable '{}' not found, install ffmpeg or set FFPROBE_EXECUTABLE",
+                executable.to_string_lossy()
+            )))
+        }
+        Err(e) => {
+            return Err(MediaResolveError::FailedProcessing(
+                anyhow::Error::new(e).context("unable to execute ffprobe process"),
+            ))
+        }
+    };
+    if !output.status.success() {
+        tracing::warn!(
+            "ffprobe exited with {}: {}",
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+        return Err(MediaResolveError::InvalidMedia);
     }
-    todo!()
+
+    tracing::info!("ffprobe succeeded");
+    Ok(serde_json::from_slice::<FfprobeOutput>(&output.stdout)
+        .map_err(|e| tracing::warn!("error interpreting ffprobe output: {e}"))
+        .unwrap_or_default()
+        .into())
 }
 
 pub async fn normalize_media_url(url: Url) -> Url {
@@ -70,7 +256,15 @@ pub async fn normalize_media_url(url: Url) -> Url {
                     if metadata.is_file() {
                         return Url::from_file_path(path).unwrap_or(url);
                     } else {
-                        return Url::from_directory_path(path).unwrap_or(url);
+                        let recursive = is_recursive(&url);
+                        return Url::from_directory_path(path)
+                            .map(|mut dir_url| {
+                                if recursive {
+                                    dir_url.set_query(Some("recursive"));
+                                }
+                                dir_url
+                            })
+                            .unwrap_or(url);
                     }
                 }
             }
@@ -83,21 +277,39 @@ pub async fn normalize_media_url(url: Url) -> Url {
 pub async fn resolve_media(url: &Url) -> Result<NewMedia<'static>, MediaResolveError> {
     if url.scheme() == "file" {
         if let Ok(path) = url.to_file_path() {
+            // ffprobe happily opens some playlist formats, so these must be
+            // left for the playlist file resolver
+            if is_playlist_file(&path) {
+                return Err(MediaResolveError::UnsupportedUrl);
+            }
             return match tokio::fs::metadata(&path).await {
                 Ok(metadata) if metadata.is_file() => {
-                    let title: Cow<'static, str> = path
-                        .file_name()
-                        .map(|name| name.to_string_lossy().into_owned().into())
-                        .unwrap_or_else(|| "<invalid basename>".into());
+                    let probe = probe_media(&path).await?;
+                    let title: Cow<'static, str> = match probe.tag("title") {
+                        Some(title) => title.to_owned().into(),
+                        None => path
+                            .file_name()
+                            .map(|name| name.to_string_lossy().into_owned().into())
+                            .unwrap_or_else(|| "<invalid basename>".into()),
+                    };
+                    let artist: Cow<'static, str> = probe
+                        .tag("artist")
+                        .or_else(|| probe.tag("album_artist"))
+                        .map(|artist| artist.to_owned().into())
+                        .unwrap_or_else(|| "<local file>".into());
                     Ok(NewMedia {
                         title,
-                        artist: "<local file>".into(),
-                        duration: get_media_duration(&path).await?,
+                        artist,
+                        duration: probe.duration,
                         url: url_from_file_path(path)
                             .await
                             .context("unable to create url for file path")?
                             .into(),
                         media_type: "local".into(),
+                        album: probe.tag("album").map(|album| album.to_owned().into()),
+                        track: probe.track(),
+                        release_date: probe.tag("date").map(|date| date.to_owned().into()),
+                        extractor: None,
                     })
                 }
                 Ok(_) => Err(MediaResolveError::InvalidMedia),
@@ -107,12 +319,12 @@ pub async fn resolve_media(url: &Url) -> Result<NewMedia<'static>, MediaResolveE
         }
     }
 
-    Err(MediaResolveError::InvalidMedia)
+    Err(MediaResolveError::UnsupportedUrl)
 }
 
 pub async fn resolve_media_list(
     url: &Url,
-) -> Result<(NewMediaList<'static>, Vec<String>), MediaResolveError> {
+) -> Result<(NewMediaList<'static>, Vec<MediaListEntry>), MediaResolveError> {
     if url.scheme() == "file" {
         if let Ok(path) = url.to_file_path() {
             return match tokio::fs::metadata(&path).await {
@@ -121,18 +333,35 @@ pub async fn resolve_media_list(
                         .file_name()
                         .map(|name| name.to_string_lossy().into_owned().into())
                         .unwrap_or_else(|| "<invalid basename>".into());
+                    let recursive = is_recursive(url);
+                    let files = {
+                        let path = path.clone();
+                        tokio::task::spawn_blocking(move || {
+                            let mut files = Vec::new();
+                            list_playable_files(&path, recursive, &mut files).map(|_| files)
+                        })
+                        .await
+                        .context("unable to join directory listing task")??
+                    };
+                    if files.is_empty() {
+                        return Err(MediaResolveError::InvalidMedia);
+                    }
+                    let media_urls = files
+                        .into_iter()
+                        .filter_map(|file| Url::from_file_path(file).ok())
+                        .map(|url| url.to_string().into())
+                        .collect();
                     return Ok((
                         NewMediaList {
                             title,
                             artist: "<local directory>".into(),
-                            url: url_from_dir_path(path)
+                            url: url_from_dir_path(path, recursive)
                                 .await
                                 .context("unable to create url for directory")?
                                 .into(),
-                            media_ids: "".into(),
                             total_duration: 0,
                         },
-                        vec![],
+                        media_urls,
                     ));
                 }
                 Ok(_) => Err(MediaResolveError::InvalidMedia),
@@ -142,5 +371,85 @@ pub async fn resolve_media_list(
         }
     }
 
-    Err(MediaResolveError::InvalidMedia)
+    Err(MediaResolveError::UnsupportedUrl)
+}
+
+pub struct LocalResolver;
+
+#[async_trait]
+impl MediaResolver for LocalResolver {
+    fn name(&self) -> &'static str {
+        "local"
+    }
+
+    fn media_type(&self) -> &'static str {
+        "local"
+    }
+
+    async fn normalize_media_url(&self, url: Url) -> Url {
+        normalize_media_url(url).await
+    }
+
+    async fn resolve_media(&self, url: &Url) -> Result<NewMedia<'static>, MediaResolveError> {
+        resolve_media(url).await
+    }
+
+    async fn resolve_media_list(
+        &self,
+        url: &Url,
+    ) -> Result<(NewMediaList<'static>, Vec<MediaListEntry>), MediaResolveError> {
+        resolve_media_list(url).await
+    }
+
+    fn get_media_thumbnail_url(&self, _media_url: &str) -> Option<String> {
+        Some("/assets/local.svg".to_owned())
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use crate::{
+        db::{media::insert_media, test_utils::TestDb},
+        resolvers::test_utils::FfprobeStub,
+    };
+
+    #[tokio::test]
+    async fn failing_ffprobe_is_invalid_media() {
+        let stub = FfprobeStub::install("echo 'Invalid data found' >&2\nexit 1").await;
+        let result = probe_media(stub.dir.join("not-a-media.txt")).await;
+        assert!(matches!(result, Err(MediaResolveError::InvalidMedia)));
+    }
+
+    #[tokio::test]
+    async fn missing_ffprobe_fails_processing() {
+        let stub = FfprobeStub::missing().await;
+        match probe_media("song.mp3").await {
+            Err(MediaResolveError::FailedProcessing(e)) => {
+                let message = e.to_string();
+                let executable = stub.dir.join("ffprobe");
+                assert!(message.contains(&format!("'{}' not found", executable.display())));
+                assert!(message.contains("FFPROBE_EXECUTABLE"));
+            }
+            _ => panic!("expected a processing failure"),
+        }
+    }
+
+    #[tokio::test]
+    async fn missing_duration_still_inserts() {
+        let stub = FfprobeStub::install(r#"echo '{"format": {"tags": {"TITLE": "song"}}}'"#).await;
+        let path = stub.dir.join("song.mp3");
+        std::fs::write(&path, b"").unwrap();
+
+        let media = resolve_media(&Url::from_file_path(&path).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(media.title, "song");
+        assert_eq!(media.duration, None);
+
+        let db = TestDb::new();
+        let mut db_conn = db.pool.get().unwrap();
+        let media = insert_media(&mut db_conn, media).unwrap();
+        assert_eq!(media.duration, None);
+    }
 }
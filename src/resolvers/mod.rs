@@ -1,11 +1,15 @@
 use anyhow::Result;
+use async_trait::async_trait;
 use thiserror::Error;
 use url::Url;
 
 use crate::db::media::{NewMedia, NewMediaList};
 
+pub mod http;
 pub mod local;
+pub mod playlist_file;
 pub mod youtube;
+pub mod ytdl;
 
 #[derive(Error, Debug)]
 pub enum MediaResolveError {
@@ -21,92 +25,306 @@ pub enum MediaResolveError {
     InvalidType,
 }
 
-pub async fn normalize_media_url(url: &str) -> Result<Url, url::ParseError> {
-    let url = Url::parse(url)?;
-    let url = youtube::normalize_media_url(url);
-    let url = local::normalize_media_url(url).await;
-    Ok(url)
+/// An entry of a resolved media list.
+pub struct MediaListEntry {
+    pub url: String,
+    /// Metadata already known from the media list itself (e.g. `#EXTINF`
+    /// lines of M3U playlists), used instead of resolving the entry again.
+    pub metadata: Option<NewMedia<'static>>,
 }
 
-pub async fn resolve_media(
-    url: &Url,
-    media_type: Option<&str>,
-) -> Result<NewMedia<'static>, MediaResolveError> {
-    let mut invalid = vec![];
-    let mut not_found = vec![];
-    macro_rules! resolve {
-        ($resolver: ident, $typename: expr) => {
-            if media_type.map(|t| t == $typename).unwrap_or(true) {
-                match $resolver::resolve_media(&url).await {
-                    Ok(media) => return Ok(media),
-                    Err(e) => {
-                        let resolver = stringify!($resolver);
-                        tracing::warn!("error resolving media by {resolver} resolver: {e}");
-                        match &e {
-                            MediaResolveError::MediaNotFound => not_found.push(resolver),
-                            MediaResolveError::InvalidMedia => invalid.push(resolver),
-                            _ => return Err(e),
-                        };
-                    }
-                };
-            }
-        };
+impl From<String> for MediaListEntry {
+    fn from(url: String) -> Self {
+        Self {
+            url,
+            metadata: None,
+        }
+    }
+}
+
+/// A source of medias (local files, YouTube, ...).
+///
+/// Every method has a default implementation that rejects the url, so a
+/// resolver only needs to implement the parts it actually supports.
+#[async_trait]
+pub trait MediaResolver: Send + Sync {
+    /// Name of the resolver, used for logging.
+    fn name(&self) -> &'static str;
+
+    /// The `media_type` of medias produced by this resolver.
+    fn media_type(&self) -> &'static str;
+
+    /// Rewrite `url` into the canonical form stored in the database, or
+    /// return it unchanged if it is not handled by this resolver.
+    async fn normalize_media_url(&self, url: Url) -> Url {
+        url
+    }
+
+    /// The url the media referenced by `url` is stored under, if resolving
+    /// `url` stores it under a different one.
+    fn media_cache_url(&self, _url: &Url) -> Option<Url> {
+        None
+    }
+
+    /// The url the media list referenced by `url` is stored under, if
+    /// resolving `url` stores it under a different one.
+    fn media_list_cache_url(&self, _url: &Url) -> Option<Url> {
+        None
+    }
+
+    async fn resolve_media(&self, _url: &Url) -> Result<NewMedia<'static>, MediaResolveError> {
+        Err(MediaResolveError::UnsupportedUrl)
+    }
+
+    async fn resolve_media_list(
+        &self,
+        _url: &Url,
+    ) -> Result<(NewMediaList<'static>, Vec<MediaListEntry>), MediaResolveError> {
+        Err(MediaResolveError::UnsupportedUrl)
     }
 
-    resolve!(local, "local");
-    resolve!(youtube, "yt");
+    fn get_media_thumbnail_url(&self, _media_url: &str) -> Option<String> {
+        None
+    }
+}
+
+/// Keeps track of the failures of every resolver tried, so that the most
+/// relevant one can be reported if none of them succeeded.
+#[derive(Default)]
+pub struct ResolveFailures {
+    unsupported: bool,
+    invalid: bool,
+    not_found: bool,
+}
 
-    if invalid.is_empty() {
-        Err(MediaResolveError::InvalidMedia)
-    } else if not_found.is_empty() {
-        Err(MediaResolveError::MediaNotFound)
-    } else {
-        Err(MediaResolveError::InvalidType)
+impl ResolveFailures {
+    /// Record `error`, or give it back if it should be reported right away.
+    pub fn push(&mut self, error: MediaResolveError) -> Result<(), MediaResolveError> {
+        match error {
+            MediaResolveError::UnsupportedUrl => self.unsupported = true,
+            MediaResolveError::InvalidMedia => self.invalid = true,
+            MediaResolveError::MediaNotFound => self.not_found = true,
+            error => return Err(error),
+        };
+        Ok(())
+    }
+
+    pub fn into_error(self) -> MediaResolveError {
+        if self.not_found {
+            MediaResolveError::MediaNotFound
+        } else if self.invalid {
+            MediaResolveError::InvalidMedia
+        } else {
+            MediaResolveError::UnsupportedUrl
+        }
     }
 }
 
-pub async fn resolve_media_list(
-    url: &Url,
-) -> Result<(NewMediaList<'static>, Vec<String>), MediaResolveError> {
-    let mut invalid = vec![];
-    let mut not_found = vec![];
-    macro_rules! resolve {
-        ($resolver: ident) => {
-            match $resolver::resolve_media_list(&url).await {
+/// The resolvers known to the app, in priority order.
+pub struct ResolverRegistry {
+    resolvers: Vec<Box<dyn MediaResolver>>,
+}
+
+impl ResolverRegistry {
+    pub fn empty() -> Self {
+        Self { resolvers: vec![] }
+    }
+
+    /// Register a resolver with lower priority than every resolver
+    /// registered before it.
+    pub fn register(&mut self, resolver: impl MediaResolver + 'static) -> &mut Self {
+        self.resolvers.push(Box::new(resolver));
+        self
+    }
+
+    pub fn resolvers(&self) -> impl Iterator<Item = &dyn MediaResolver> {
+        self.resolvers.iter().map(AsRef::as_ref)
+    }
+
+    pub fn resolver_for_type(&self, media_type: &str) -> Option<&dyn MediaResolver> {
+        self.resolvers().find(|r| r.media_type() == media_type)
+    }
+
+    pub async fn normalize_media_url(&self, url: &str) -> Result<Url, url::ParseError> {
+        // allow scheme-less YouTube urls such as the `yt.be/search:` preset,
+        // but not relative paths that would be taken for hosts
+        let mut url = match Url::parse(url) {
+            Err(url::ParseError::RelativeUrlWithoutBase) => Url::parse(&format!("https://{url}"))
+                .ok()
+                .filter(youtube::is_youtube_url)
+                .ok_or(url::ParseError::RelativeUrlWithoutBase)?,
+            result => result?,
+        };
+        for resolver in self.resolvers() {
+            url = resolver.normalize_media_url(url).await;
+        }
+        Ok(url)
+    }
+
+    /// The url to look the media, or the media list if `list` is set,
+    /// referenced by the normalized `url` up with in the database.
+    pub fn cache_url(&self, url: &Url, list: bool) -> Url {
+        self.resolvers()
+            .find_map(|resolver| {
+                if list {
+                    resolver.media_list_cache_url(url)
+                } else {
+                    resolver.media_cache_url(url)
+                }
+            })
+            .unwrap_or_else(|| url.clone())
+    }
+
+    pub async fn resolve_media(
+        &self,
+        url: &Url,
+        media_type: Option<&str>,
+    ) -> Result<NewMedia<'static>, MediaResolveError> {
+        let mut failures = ResolveFailures::default();
+        let mut type_matched = false;
+        for resolver in self.resolvers() {
+            if media_type
+                .map(|t| t != resolver.media_type())
+                .unwrap_or(false)
+            {
+                continue;
+            }
+            type_matched = true;
+            match resolver.resolve_media(url).await {
+                Ok(media) => return Ok(media),
+                Err(e) => {
+                    let name = resolver.name();
+                    tracing::warn!("error resolving media by {name} resolver: {e}");
+                    failures.push(e)?;
+                }
+            }
+        }
+
+        if type_matched {
+            Err(failures.into_error())
+        } else {
+            Err(MediaResolveError::InvalidType)
+        }
+    }
+
+    pub async fn resolve_media_list(
+        &self,
+        url: &Url,
+    ) -> Result<(NewMediaList<'static>, Vec<MediaListEntry>), MediaResolveError> {
+        let mut failures = ResolveFailures::default();
+        for resolver in self.resolvers() {
+            match resolver.resolve_media_list(url).await {
                 Ok(media_list) => return Ok(media_list),
                 Err(e) => {
-                    let resolver = stringify!($resolver);
-                    tracing::warn!("error resolving media list by {resolver} resolver: {e}");
-                    match &e {
-                        MediaResolveError::MediaNotFound => not_found.push(resolver),
-                        MediaResolveError::InvalidMedia => invalid.push(resolver),
-                        _ => return Err(e),
-                    };
+                    let name = resolver.name();
+                    tracing::warn!("error resolving media list by {name} resolver: {e}");
+                    failures.push(e)?;
                 }
-            };
-        };
+            }
+        }
+
+        Err(failures.into_error())
     }
 
-    resolve!(local);
-    resolve!(youtube);
+    pub fn get_media_thumbnail_url(&self, media_type: &str, media_url: &str) -> Option<String> {
+        self.resolver_for_type(media_type)
+            .and_then(|resolver| resolver.get_media_thumbnail_url(media_url))
+    }
+}
 
-    if invalid.is_empty() {
-        Err(MediaResolveError::InvalidMedia)
-    } else if not_found.is_empty() {
-        Err(MediaResolveError::MediaNotFound)
-    } else {
-        unreachable!()
+impl Default for ResolverRegistry {
+    fn default() -> Self {
+        let mut registry = Self::empty();
+        registry
+            .register(local::LocalResolver)
+            .register(playlist_file::PlaylistFileResolver)
+            .register(youtube::YoutubeResolver)
+            .register(http::HttpResolver)
+            // yt-dlp accepts almost any url, so it must come last
+            .register(ytdl::YtdlResolver);
+        registry
     }
 }
 
-pub fn get_media_thumbnail_url(media_type: &str, media_url: &str) -> Option<String> {
-    if media_type == "yt" {
-        return youtube::get_media_thumbnail_url(media_url);
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn only_youtube_urls_may_omit_their_scheme() {
+        let resolvers = ResolverRegistry::default();
+        for url in [
+            "music/song.mp3",
+            "song.mp3",
+            "example.com/song.mp3",
+            "/song.mp3",
+        ] {
+            assert_eq!(
+                resolvers.normalize_media_url(url).await,
+                Err(url::ParseError::RelativeUrlWithoutBase),
+                "normalizing {url}"
+            );
+        }
+        assert_eq!(
+            resolvers
+                .normalize_media_url("yt.be/search:song")
+                .await
+                .unwrap()
+                .as_str(),
+            "https://yt.be/search:song"
+        );
     }
+}
+
+#[cfg(all(test, unix))]
+pub(crate) mod test_utils {
+    use std::{os::unix::fs::PermissionsExt, path::PathBuf, time::SystemTime};
+    use tokio::sync::{Mutex, MutexGuard};
 
-    if media_type == "local" {
-        return Some("/assets/local.svg".to_owned());
+    /// `FFPROBE_EXECUTABLE` is shared by the whole process.
+    static FFPROBE_LOCK: Mutex<()> = Mutex::const_new(());
+
+    /// Points `FFPROBE_EXECUTABLE` at an `ffprobe` script in a temporary
+    /// directory for as long as the stub lives. Only one stub exists at a
+    /// time.
+    pub struct FfprobeStub {
+        pub dir: PathBuf,
+        _lock: MutexGuard<'static, ()>,
     }
 
-    None
+    impl FfprobeStub {
+        async fn new(script: Option<&str>) -> Self {
+            let lock = FFPROBE_LOCK.lock().await;
+            let nanos = SystemTime::now()
+                .duration_since(SystemTime::UNIX_EPOCH)
+                .unwrap()
+                .as_nanos();
+            let dir = std::env::temp_dir().join(format!("plst3-ffprobe-{nanos}"));
+            std::fs::create_dir(&dir).unwrap();
+            let executable = dir.join("ffprobe");
+            if let Some(script) = script {
+                std::fs::write(&executable, format!("#!/bin/sh\n{script}\n")).unwrap();
+                std::fs::set_permissions(&executable, std::fs::Permissions::from_mode(0o755))
+                    .unwrap();
+            }
+            std::env::set_var("FFPROBE_EXECUTABLE", &executable);
+            Self { dir, _lock: lock }
+        }
+
+        /// An `ffprobe` that runs the shell `script`.
+        pub async fn install(script: &str) -> Self {
+            Self::new(Some(script)).await
+        }
+
+        /// An `ffprobe` that does not exist.
+        pub async fn missing() -> Self {
+            Self::new(None).await
+        }
+    }
+
+    impl Drop for FfprobeStub {
+        fn drop(&mut self) {
+            std::fs::remove_dir_all(&self.dir).ok();
+        }
+    }
 }
@@ -0,0 +1,483 @@
+use std::{borrow::Cow, collections::BTreeMap, ffi::OsStr, io::ErrorKind, path::Path};
+
+use anyhow::{anyhow, Result};
+use async_trait::async_trait;
+use quick_xml::{events::Event, Reader};
+use url::Url;
+
+use crate::db::media::{NewMedia, NewMediaList};
+
+use super::{local::is_playable, MediaListEntry, MediaResolveError, MediaResolver};
+
+const PLAYLIST_EXTENSIONS: &[&str] = &["m3u", "m3u8", "pls", "xspf"];
+
+pub fn is_playlist_file(path: &Path) -> bool {
+    path.extension()
+        .and_then(OsStr::to_str)
+        .map(|ext| PLAYLIST_EXTENSIONS.contains(&ext.to_ascii_lowercase().as_str()))
+        .unwrap_or_default()
+}
+
+/// An entry as written in the playlist file, before its location is resolved.
+#[derive(Default)]
+struct RawEntry {
+    location: String,
+    title: Option<String>,
+    artist: Option<String>,
+    album: Option<String>,
+    track: Option<i32>,
+    duration: Option<i32>,
+}
+
+#[derive(Default)]
+struct RawPlaylist {
+    title: Option<String>,
+    creator: Option<String>,
+    entries: Vec<RawEntry>,
+}
+
+/// Parse a duration in `unit`s of a second, rounded to seconds. Negative
+/// durations (e.g. `-1` for streams) are unknown.
+fn parse_duration(duration: &str, unit: f64) -> Option<i32> {
+    duration
+        .trim()
+        .parse::<f64>()
+        .ok()
+        .filter(|duration| *duration >= 0.0)
+        .map(|duration| (duration * unit).round() as i32)
+}
+
+fn parse_seconds(duration: &str) -> Option<i32> {
+    parse_duration(duration, 1.0)
+}
+
+/// `#EXTINF:<duration> <attributes>,<artist> - <title>`, where attribute
+/// values are quoted and may contain commas.
+fn parse_extinf(info: &str, entry: &mut RawEntry) {
+    let mut quoted = false;
+    let comma = info.find(|c| match c {
+        '"' => {
+            quoted = !quoted;
+            false
+        }
+        ',' => !quoted,
+        _ => false,
+    });
+    let (duration, name) = match comma {
+        Some(comma) => (&info[..comma], &info[comma + 1..]),
+        None => (info, ""),
+    };
+    entry.duration = duration.split_whitespace().next().and_then(parse_seconds);
+    let name = name.trim();
+    match name.split_once(" - ") {
+        Some((artist, title)) => {
+            entry.artist = Some(artist.trim().to_owned());
+            entry.title = Some(title.trim().to_owned());
+        }
+        None if !name.is_empty() => entry.title = Some(name.to_owned()),
+        None => {}
+    }
+}
+
+fn parse_m3u(content: &str) -> RawPlaylist {
+    let mut playlist = RawPlaylist::default();
+    let mut entry = RawEntry::default();
+    for line in content.lines().map(str::trim) {
+        if let Some(info) = line.strip_prefix("#EXTINF:") {
+            parse_extinf(info, &mut entry);
+        } else if let Some(album) = line.strip_prefix("#EXTALB:") {
+            entry.album = Some(album.trim().to_owned());
+        } else if let Some(title) = line.strip_prefix("#PLAYLIST:") {
+            playlist.title = Some(title.trim().to_owned());
+        } else if !line.is_empty() && !line.starts_with('#') {
+            entry.location = line.to_owned();
+            playlist.entries.push(std::mem::take(&mut entry));
+        }
+    }
+    playlist
+}
+
+fn parse_pls(content: &str) -> RawPlaylist {
+    let mut entries = BTreeMap::<u32, RawEntry>::new();
+    for line in content.lines().map(str::trim) {
+        let Some((key, value)) = line.split_once('=') else {
+            continue;
+        };
+        let key = key.trim().to_ascii_lowercase();
+        let value = value.trim();
+        let field = ["file", "title", "length"]
+            .into_iter()
+            .find_map(|field| Some((field, key.strip_prefix(field)?.parse::<u32>().ok()?)));
+        if let Some((field, index)) = field {
+            let entry = entries.entry(index).or_default();
+            match field {
+                "file" => entry.location = value.to_owned(),
+                "title" => entry.title = Some(value.to_owned()),
+                _ => entry.duration = parse_seconds(value),
+            }
+        }
+    }
+
+    RawPlaylist {
+        entries: entries
+            .into_values()
+            .filter(|entry| !entry.location.is_empty())
+            .collect(),
+        ..Default::default()
+    }
+}
+
+fn parse_xspf(content: &str) -> Result<RawPlaylist> {
+    let mut reader = Reader::from_str(content);
+    reader.trim_text(true);
+    let mut playlist = RawPlaylist::default();
+    let mut entry: Option<RawEntry> = None;
+    let mut path = Vec::<String>::new();
+    loop {
+        match reader.read_event()? {
+            Event::Start(element) => {
+                let name = String::from_utf8_lossy(element.local_name().as_ref()).into_owned();
+                if name == "track" {
+                    entry = Some(RawEntry::default());
+                }
+                path.push(name);
+            }
+            Event::End(_) => {
+                let name = path.pop();
+                if name.as_deref() == Some("track") {
+                    playlist.entries.extend(entry.take());
+                }
+            }
+            Event::Text(text) => {
+                let text = text.unescape()?.into_owned();
+                let field = path.last().map(String::as_str);
+                match (entry.as_mut(), field) {
+                    (Some(entry), Some("location")) => entry.location = text,
+                    (Some(entry), Some("title")) => entry.title = Some(text),
+                    (Some(entry), Some("creator")) => entry.artist = Some(text),
+                    (Some(entry), Some("album")) => entry.album = Some(text),
+                    (Some(entry), Some("trackNum")) => entry.track = text.trim().parse().ok(),
+                    // durations are in milliseconds
+                    (Some(entry), Some("duration")) => {
+                        entry.duration = parse_duration(&text, 0.001)
+                    }
+                    (None, Some("title")) if path.len() == 2 => playlist.title = Some(text),
+                    (None, Some("creator")) if path.len() == 2 => playlist.creator = Some(text),
+                    _ => {}
+                }
+            }
+            Event::Eof => break,
+            _ => {}
+        }
+    }
+
+    playlist.entries.retain(|entry| !entry.location.is_empty());
+    Ok(playlist)
+}
+
+/// Resolve a location in a M3U or PLS file, which is either an url or a
+/// path relative to the playlist file.
+fn resolve_path_location(playlist_path: &Path, location: &str) -> Option<Url> {
+    // single letter schemes are windows drive letters
+    match Url::parse(location) {
+        Ok(url) if url.scheme().len() > 1 => return Some(url),
+        _ => {}
+    }
+
+    let path = Path::new(location);
+    if path.is_absolute() {
+        Url::from_file_path(path).ok()
+    } else {
+        Url::from_file_path(playlist_path.parent()?.join(path)).ok()
+    }
+}
+
+/// Use the metadata of an entry if it is an existing local file and the
+/// playlist has everything ffprobe would have been used for.
+async fn entry_metadata(url: &Url, entry: &mut RawEntry) -> Option<NewMedia<'static>> {
+    let path = url.to_file_path().ok()?;
+    if !is_playable(&path) || !tokio::fs::metadata(&path).await.ok()?.is_file() {
+        return None;
+    }
+
+    let duration = entry.duration?;
+    Some(NewMedia {
+        title: entry.title.take()?.into(),
+        artist: entry
+            .artist
+            .take()
+            .map(Cow::Owned)
+            .unwrap_or_else(|| "<local file>".into()),
+        duration: Some(duration),
+        url: url.to_string().into(),
+        media_type: "local".into(),
+        album: entry.album.take().map(Cow::Owned),
+        track: entry.track,
+        release_date: None,
+        extractor: None,
+    })
+}
+
+pub async fn resolve_media_list(
+    url: &Url,
+) -> Result<(NewMediaList<'static>, Vec<MediaListEntry>), MediaResolveError> {
+    let Some(path) = url
+        .to_file_path()
+        .ok()
+        .filter(|path| url.scheme() == "file" && is_playlist_file(path))
+    else {
+        return Err(MediaResolveError::UnsupportedUrl);
+    };
+
+    let content = match tokio::fs::read(&path).await {
+        Ok(content) => content,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Err(MediaResolveError::MediaNotFound),
+        Err(e) => return Err(MediaResolveError::FailedProcessing(e.into())),
+    };
+    // M3U files are not necessarily UTF-8, but most of them are
+    let content = String::from_utf8_lossy(&content);
+    let extension = path
+        .extension()
+        .and_then(OsStr::to_str)
+        .unwrap_or_default()
+        .to_ascii_lowercase();
+    let playlist = match extension.as_str() {
+        "pls" => parse_pls(&content),
+        "xspf" => parse_xspf(&content).map_err(|e| {
+            tracing::warn!("error parsing xspf playlist {}: {e}", path.display());
+            MediaResolveError::InvalidMedia
+        })?,
+        _ => parse_m3u(&content),
+    };
+
+    let mut entries = Vec::with_capacity(playlist.entries.len());
+    for mut entry in playlist.entries {
+        let location = if extension == "xspf" {
+            // xspf locations are uris, relative to the playlist file
+            url.join(&entry.location).ok()
+        } else {
+            resolve_path_location(&path, &entry.location)
+        };
+        let Some(location) = location else {
+            tracing::warn!("skipping invalid playlist entry: {}", entry.location);
+            continue;
+        };
+        entries.push(MediaListEntry {
+            metadata: entry_metadata(&location, &mut entry).await,
+            url: location.to_string(),
+        });
+    }
+    if entries.is_empty() {
+        return Err(MediaResolveError::InvalidMedia);
+    }
+
+    let title = playlist
+        .title
+        .or_else(|| {
+            path.file_stem()
+                .map(|name| name.to_string_lossy().into_owned())
+        })
+        .ok_or_else(|| anyhow!("invalid playlist file name"))?;
+    Ok((
+        NewMediaList {
+            title: title.into(),
+            artist: playlist
+                .creator
+                .map(Cow::Owned)
+                .unwrap_or_else(|| "<playlist file>".into()),
+            url: url.to_string().into(),
+            total_duration: 0,
+        },
+        entries,
+    ))
+}
+
+/// M3U/M3U8, PLS and XSPF playlist files on the local filesystem.
+pub struct PlaylistFileResolver;
+
+#[async_trait]
+impl MediaResolver for PlaylistFileResolver {
+    fn name(&self) -> &'static str {
+        "playlist file"
+    }
+
+    fn media_type(&self) -> &'static str {
+        "playlist_file"
+    }
+
+    async fn resolve_media_list(
+        &self,
+        url: &Url,
+    ) -> Result<(NewMediaList<'static>, Vec<MediaListEntry>), MediaResolveError> {
+        resolve_media_list(url).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::SystemTime;
+
+    type Summary<'a> = (&'a str, Option<&'a str>, Option<&'a str>, Option<i32>);
+
+    fn summary(playlist: &RawPlaylist) -> Vec<Summary<'_>> {
+        playlist
+            .entries
+            .iter()
+            .map(|entry| {
+                (
+                    entry.location.as_str(),
+                    entry.title.as_deref(),
+                    entry.artist.as_deref(),
+                    entry.duration,
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn m3u_files_are_parsed() {
+        let playlist = parse_m3u(concat!(
+            "#EXTM3U\n",
+            "#PLAYLIST:Road trip\n",
+            "#EXTINF:123 tvg-id=\"a\" tvg-logo=\"http://example.com/a,b.png\",Artist - Title\n",
+            "#EXTALB:Album\n",
+            "song.mp3\n",
+            "\n",
+            "#EXTINF:-1,Radio stream\n",
+            "http://radio.example/stream\n",
+            "#EXTINF:5.6,\n",
+            "/music/other.ogg\n",
+            "# a comment\n",
+            "no-info.flac\n",
+        ));
+        assert_eq!(playlist.title.as_deref(), Some("Road trip"));
+        assert_eq!(playlist.entries[0].album.as_deref(), Some("Album"));
+        assert_eq!(
+            summary(&playlist),
+            [
+                ("song.mp3", Some("Title"), Some("Artist"), Some(123)),
+                (
+                    "http://radio.example/stream",
+                    Some("Radio stream"),
+                    None,
+                    None
+                ),
+                ("/music/other.ogg", None, None, Some(6)),
+                ("no-info.flac", None, None, None),
+            ]
+        );
+    }
+
+    #[test]
+    fn pls_files_are_parsed() {
+        let playlist = parse_pls(concat!(
+            "[playlist]\n",
+            "File2=http://radio.example/two\n",
+            "Title2=Second\n",
+            "Length2=-1\n",
+            "file1 = one.mp3\n",
+            "Title1=First\n",
+            "Length1=61\n",
+            "Title3=No file\n",
+            "NumberOfEntries=3\n",
+            "Version=2\n",
+        ));
+        assert_eq!(
+            summary(&playlist),
+            [
+                ("one.mp3", Some("First"), None, Some(61)),
+                ("http://radio.example/two", Some("Second"), None, None),
+            ]
+        );
+    }
+
+    const XSPF: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<playlist version="1" xmlns="http://xspf.org/ns/0/">
+  <title>Rock &amp; Roll</title>
+  <creator>Someone</creator>
+  <trackList>
+    <track>
+      <location>songs/a%20b.mp3</location>
+      <title>Tom &amp; Jerry &lt;live&gt;</title>
+      <creator>Band</creator>
+      <album>Album</album>
+      <trackNum>3</trackNum>
+      <duration>61499</duration>
+    </track>
+    <track>
+      <location>http://example.com/long.ogg</location>
+      <duration>99999999999999</duration>
+    </track>
+    <track>
+      <title>No location</title>
+    </track>
+  </trackList>
+</playlist>"#;
+
+    #[test]
+    fn xspf_files_are_parsed() {
+        let playlist = parse_xspf(XSPF).unwrap();
+        assert_eq!(playlist.title.as_deref(), Some("Rock & Roll"));
+        assert_eq!(playlist.creator.as_deref(), Some("Someone"));
+        assert_eq!(playlist.entries[0].album.as_deref(), Some("Album"));
+        assert_eq!(playlist.entries[0].track, Some(3));
+        assert_eq!(
+            summary(&playlist),
+            [
+                (
+                    "songs/a%20b.mp3",
+                    Some("Tom & Jerry <live>"),
+                    Some("Band"),
+                    Some(61)
+                ),
+                ("http://example.com/long.ogg", None, None, Some(i32::MAX)),
+            ]
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn path_locations_are_resolved() {
+        let playlist_path = Path::new("/music/lists/road.m3u");
+        let cases = [
+            ("song.mp3", "file:///music/lists/song.mp3"),
+            ("sub dir/song.mp3", "file:///music/lists/sub%20dir/song.mp3"),
+            ("/music/song.flac", "file:///music/song.flac"),
+            ("file:///music/song.ogg", "file:///music/song.ogg"),
+            ("http://radio.example/stream", "http://radio.example/stream"),
+        ];
+        for (location, expected) in cases {
+            assert_eq!(
+                resolve_path_location(playlist_path, location).map(String::from),
+                Some(expected.to_owned()),
+                "resolving {location}"
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn xspf_locations_are_relative_to_the_file() {
+        let nanos = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap()
+            .as_nanos();
+        let dir = std::env::temp_dir().join(format!("plst3-xspf-{nanos}"));
+        std::fs::create_dir(&dir).unwrap();
+        let path = dir.join("list.xspf");
+        std::fs::write(&path, XSPF).unwrap();
+
+        let url = Url::from_file_path(&path).unwrap();
+        let result = resolve_media_list(&url).await;
+        std::fs::remove_dir_all(&dir).ok();
+        let (media_list, entries) = result.unwrap();
+        assert_eq!(media_list.title, "Rock & Roll");
+        assert_eq!(
+            entries.iter().map(|e| e.url.as_str()).collect::<Vec<_>>(),
+            [
+                url.join("songs/a%20b.mp3").unwrap().as_str(),
+                "http://example.com/long.ogg",
+            ]
+        );
+    }
+}
@@ -1,29 +1,17 @@
 use std::borrow::Cow;
 
 use anyhow::Result;
-use lazy_static::lazy_static;
-use url::Url;
-use youtube_dl::{YoutubeDl, YoutubeDlOutput};
+use async_trait::async_trait;
+use percent_encoding::{percent_decode_str, utf8_percent_encode, NON_ALPHANUMERIC};
+use url::{Position, Url};
+use youtube_dl::{SingleVideo, YoutubeDlOutput};
 
 use crate::db::media::{NewMedia, NewMediaList};
 
-use super::MediaResolveError;
-
-lazy_static! {
-    static ref FORCE_IPV4: bool = std::env::var("YTDL_FORCE_IPV4")
-        .ok()
-        .and_then(|env| env.parse::<bool>().ok())
-        .unwrap_or_default();
-}
-
-async fn run_ytdl(url: impl Into<String>) -> Result<YoutubeDlOutput, youtube_dl::Error> {
-    let mut builder = YoutubeDl::new(url);
-    builder.extra_arg("--ignore-no-formats-error");
-    if *FORCE_IPV4 {
-        builder.extra_arg("--force-ipv4");
-    }
-    builder.run_async().await
-}
+use super::{
+    ytdl::{run_ytdl, ytdl_builder},
+    MediaListEntry, MediaResolveError, MediaResolver,
+};
 
 pub fn youtube_video_url_string(id: &str) -> String {
     format!("https://youtu.be/{id}")
@@ -33,6 +21,13 @@ pub fn youtube_list_url_string(id: &str) -> String {
     format!("https://youtube.com/playlist?list={id}")
 }
 
+/// `count` is `None` for searches resolving to the first hit only.
+pub fn youtube_search_url_string(query: &str, count: Option<usize>) -> String {
+    let count = count.map(|c| c.to_string()).unwrap_or_default();
+    let query = utf8_percent_encode(query, NON_ALPHANUMERIC);
+    format!("https://yt.be/search{count}:{query}")
+}
+
 pub fn youtube_video_url(id: &str) -> Url {
     Url::parse(&youtube_video_url_string(id))
         .expect("invalid id, sanitize with check_video_id first")
@@ -42,9 +37,31 @@ pub fn youtube_list_url(id: &str) -> Url {
     Url::parse(&youtube_list_url_string(id)).expect("invalid id, sanitize with check_list_id first")
 }
 
+pub fn youtube_video_in_list_url(video_id: &str, list_id: &str) -> Url {
+    let mut url = youtube_video_url(video_id);
+    url.query_pairs_mut().append_pair("list", list_id);
+    url
+}
+
+pub fn youtube_search_url(query: &str, count: Option<usize>) -> Url {
+    Url::parse(&youtube_search_url_string(query, count)).expect("search query is percent-encoded")
+}
+
 pub enum YoutubeUrlParseResult<'a> {
     Video(Cow<'a, str>),
     Playlist(Cow<'a, str>),
+    /// `watch?v=<video_id>&list=<list_id>`, which resolves to the video as a
+    /// media and to the whole playlist as a media list.
+    VideoInPlaylist {
+        video_id: Cow<'a, str>,
+        list_id: Cow<'a, str>,
+    },
+    /// `yt.be/search:<query>` resolves to the first hit, while
+    /// `yt.be/search<N>:<query>` resolves to a list of the top N hits.
+    Search {
+        query: Cow<'a, str>,
+        count: Option<usize>,
+    },
     Invalid,
 }
 
@@ -61,86 +78,165 @@ fn check_list_id(id: &str) -> bool {
         .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
 }
 
-pub fn check_normalized_youtube_url(url: &Url) -> YoutubeUrlParseResult {
-    if url.scheme() != "https" {
+const MAX_SEARCH_COUNT: usize = 50;
+
+fn check_search_url(url: &Url) -> Option<(Cow<'_, str>, Option<usize>)> {
+    if url.host_str() != Some("yt.be") && url.host_str() != Some("youtu.be") {
+        return None;
+    }
+
+    // search terms may contain '?' or '#', so take everything after the
+    // host instead of just the path
+    let rest = url[Position::BeforePath..].strip_prefix("/search")?;
+    let (count, query) = rest.split_once(':')?;
+    let count = if count.is_empty() {
+        None
+    } else {
+        Some(count.parse::<usize>().ok()?.clamp(1, MAX_SEARCH_COUNT))
+    };
+    let query = percent_decode_str(query).decode_utf8().ok()?;
+    if query.trim().is_empty() {
+        return None;
+    }
+    Some((query, count))
+}
+
+const SHORT_HOSTS: &[&str] = &["youtu.be", "www.youtu.be", "yt.be"];
+const HOSTS: &[&str] = &[
+    "youtube.com",
+    "www.youtube.com",
+    "m.youtube.com",
+    "music.youtube.com",
+    "youtube-nocookie.com",
+    "www.youtube-nocookie.com",
+];
+
+fn query_param<'a>(url: &'a Url, name: &str) -> Option<Cow<'a, str>> {
+    url.query_pairs()
+        .find(|(key, _)| key == name)
+        .map(|(_, value)| value)
+}
+
+pub fn check_normalized_youtube_url(url: &Url) -> YoutubeUrlParseResult<'_> {
+    if url.scheme() != "https" && url.scheme() != "http" {
         return YoutubeUrlParseResult::Invalid;
     }
+    let Some(host) = url.host_str() else {
+        return YoutubeUrlParseResult::Invalid;
+    };
 
-    {
-        let path = &url.path()[1..];
-        if check_video_id(path)
-            && (url.host_str() == Some("youtu.be") || url.host_str() == Some("yt.be"))
-        {
-            return YoutubeUrlParseResult::Video(path.into());
-        }
+    if let Some((query, count)) = check_search_url(url) {
+        return YoutubeUrlParseResult::Search { query, count };
     }
 
-    {
-        let video_id = url
-            .query_pairs()
-            .find(|(key, _)| key == "v")
-            .map(|(_, value)| value)
-            .filter(|id| check_video_id(id));
-        if let Some(video_id) = video_id {
-            tracing::info!("{url:?}");
-            if url.host_str() == Some("youtube.com") || url.host_str() == Some("www.youtube.com") {
-                return YoutubeUrlParseResult::Video(video_id);
-            }
+    let mut segments = url.path_segments().into_iter().flatten();
+    let video_id = if SHORT_HOSTS.contains(&host) {
+        segments.next().map(Cow::Borrowed)
+    } else if HOSTS.contains(&host) {
+        match segments.next() {
+            Some("watch") => query_param(url, "v"),
+            // `videoseries` happens to be a valid video id
+            Some("embed") => segments
+                .next()
+                .filter(|id| *id != "videoseries")
+                .map(Cow::Borrowed),
+            Some("shorts" | "live" | "v") => segments.next().map(Cow::Borrowed),
+            _ => None,
         }
-    }
+    } else {
+        return YoutubeUrlParseResult::Invalid;
+    };
 
-    {
-        let path = url.path();
-        let list_id = url
-            .query_pairs()
-            .find(|(key, _)| key == "list")
-            .map(|(_, value)| value);
-        if path == "/playlist"
-            && (url.host_str() == Some("youtube.com") || url.host_str() == Some("www.youtube.com"))
-        {
-            if let Some(id) = list_id.filter(|id| check_list_id(id)) {
-                return YoutubeUrlParseResult::Playlist(id.into_owned().into());
-            }
+    let video_id = video_id.filter(|id| check_video_id(id));
+    let list_id = query_param(url, "list").filter(|id| !id.is_empty() && check_list_id(id));
+    match (video_id, list_id) {
+        (Some(video_id), Some(list_id)) => YoutubeUrlParseResult::VideoInPlaylist {
+            video_id: video_id.into_owned().into(),
+            list_id: list_id.into_owned().into(),
+        },
+        (Some(video_id), None) => YoutubeUrlParseResult::Video(video_id.into_owned().into()),
+        // `/embed/videoseries?list=` is the embedded form of a playlist
+        (None, Some(list_id)) if HOSTS.contains(&host) => {
+            YoutubeUrlParseResult::Playlist(list_id.into_owned().into())
         }
+        _ => YoutubeUrlParseResult::Invalid,
     }
+}
 
-    YoutubeUrlParseResult::Invalid
+/// Whether `url` is handled by the YouTube resolver, so other resolvers can
+/// skip it.
+pub fn is_youtube_url(url: &Url) -> bool {
+    !matches!(
+        check_normalized_youtube_url(url),
+        YoutubeUrlParseResult::Invalid
+    )
 }
 
 pub fn normalize_media_url(url: Url) -> Url {
     match check_normalized_youtube_url(&url) {
         YoutubeUrlParseResult::Video(id) => youtube_video_url(&id),
         YoutubeUrlParseResult::Playlist(id) => youtube_list_url(&id),
+        YoutubeUrlParseResult::VideoInPlaylist { video_id, list_id } => {
+            youtube_video_in_list_url(&video_id, &list_id)
+        }
+        YoutubeUrlParseResult::Search { query, count } => youtube_search_url(&query, count),
         YoutubeUrlParseResult::Invalid => url,
     }
 }
 
+fn new_media_from_video(video: SingleVideo, url: String) -> NewMedia<'static> {
+    NewMedia {
+        title: video
+            .title
+            .map(Cow::Owned)
+            .unwrap_or("<empty youtube title>".into()),
+        artist: video
+            .artist
+            .or(video.channel)
+            .or(video.uploader)
+            .map(Cow::Owned)
+            .unwrap_or("<empty youtube channel>".into()),
+        duration: video
+            .duration
+            .and_then(|v| v.as_f64())
+            .map(|v| v.round() as i32),
+        url: url.into(),
+        media_type: "yt".into(),
+        album: video.album.map(Cow::Owned),
+        track: None,
+        release_date: None,
+        extractor: None,
+    }
+}
+
 pub async fn resolve_media(url: &Url) -> Result<NewMedia<'static>, MediaResolveError> {
-    if !matches!(
-        check_normalized_youtube_url(url),
-        YoutubeUrlParseResult::Video(_)
-    ) {
-        return Err(MediaResolveError::UnsupportedUrl);
-    }
-    match run_ytdl(url.as_str()).await {
-        Ok(YoutubeDlOutput::SingleVideo(video)) => Ok(NewMedia {
-            title: video
-                .title
-                .map(Cow::Owned)
-                .unwrap_or("<empty youtube title>".into()),
-            artist: video
-                .artist
-                .or(video.channel)
-                .or(video.uploader)
-                .map(Cow::Owned)
-                .unwrap_or("<empty youtube channel>".into()),
-            duration: video
-                .duration
-                .and_then(|v| v.as_f64())
-                .map(|v| v.round() as i32),
-            url: url.to_string().into(),
-            media_type: "yt".into(),
-        }),
+    let video_id = match check_normalized_youtube_url(url) {
+        YoutubeUrlParseResult::Video(id) => id,
+        YoutubeUrlParseResult::VideoInPlaylist { video_id, .. } => video_id,
+        YoutubeUrlParseResult::Search { query, count: None } => {
+            return match run_ytdl(format!("ytsearch1:{query}")).await {
+                Ok(YoutubeDlOutput::Playlist(playlist)) => playlist
+                    .entries
+                    .unwrap_or_default()
+                    .into_iter()
+                    .next()
+                    .map(|video| {
+                        let url = youtube_video_url_string(&video.id);
+                        new_media_from_video(video, url)
+                    })
+                    .ok_or(MediaResolveError::MediaNotFound),
+                Ok(_) => Err(MediaResolveError::InvalidMedia),
+                Err(youtube_dl::Error::Json(_)) => Err(MediaResolveError::MediaNotFound),
+                Err(e) => Err(MediaResolveError::FailedProcessing(e.into())),
+            };
+        }
+        YoutubeUrlParseResult::Search { .. } => return Err(MediaResolveError::InvalidMedia),
+        _ => return Err(MediaResolveError::UnsupportedUrl),
+    };
+    // drop the list parameter, otherwise yt-dlp would fetch the whole playlist
+    let video_url = youtube_video_url_string(&video_id);
+    match run_ytdl(video_url.as_str()).await {
+        Ok(YoutubeDlOutput::SingleVideo(video)) => Ok(new_media_from_video(*video, video_url)),
         Ok(_) => Err(MediaResolveError::InvalidMedia),
         Err(youtube_dl::Error::Json(_)) => Err(MediaResolveError::MediaNotFound),
         Err(e) => Err(MediaResolveError::FailedProcessing(e.into())),
@@ -149,43 +245,67 @@ pub async fn resolve_media(url: &Url) -> Result<NewMedia<'static>, MediaResolveE
 
 pub async fn resolve_media_list(
     url: &Url,
-) -> Result<(NewMediaList<'static>, Vec<String>), MediaResolveError> {
-    if !matches!(
-        check_normalized_youtube_url(url),
-        YoutubeUrlParseResult::Playlist(_)
-    ) {
-        return Err(MediaResolveError::UnsupportedUrl);
-    }
-    match run_ytdl(url.as_str()).await {
+) -> Result<(NewMediaList<'static>, Vec<MediaListEntry>), MediaResolveError> {
+    let (ytdl_url, search_title) = match check_normalized_youtube_url(url) {
+        YoutubeUrlParseResult::Playlist(id)
+        | YoutubeUrlParseResult::VideoInPlaylist { list_id: id, .. } => {
+            (youtube_list_url_string(&id), None)
+        }
+        YoutubeUrlParseResult::Search {
+            query,
+            count: Some(count),
+        } => (
+            format!("ytsearch{count}:{query}"),
+            Some(format!("YouTube search: {query}")),
+        ),
+        YoutubeUrlParseResult::Search { count: None, .. } => {
+            return Err(MediaResolveError::InvalidMedia)
+        }
+        _ => return Err(MediaResolveError::UnsupportedUrl),
+    };
+    // searches are cached by their own url, playlists by the playlist url
+    let list_url = match search_title {
+        Some(_) => url.to_string(),
+        None => ytdl_url.clone(),
+    };
+    // flat playlists only take a single request, and the entries usually
+    // have enough metadata to not be resolved again
+    let mut builder = ytdl_builder(ytdl_url);
+    builder.flat_playlist(true);
+    match builder.run_async().await {
         Ok(YoutubeDlOutput::Playlist(playlist)) => Ok((
             NewMediaList {
-                title: playlist
-                    .title
+                title: search_title
+                    .or(playlist.title)
                     .map(Cow::Owned)
                     .unwrap_or("<empty youtube title>".into()),
                 artist: playlist
                     .uploader
                     .map(Cow::Owned)
                     .unwrap_or("<empty youtube channel>".into()),
-                url: url.to_string().into(),
-                media_ids: "".into(),
-                total_duration: playlist
-                    .entries
-                    .as_ref()
-                    .map(|p| {
-                        p.iter()
-                            .filter_map(|video| video.duration.as_ref())
-                            .filter_map(|duration| duration.as_f64())
-                            .map(|seconds| seconds.round() as i32)
-                            .sum()
-                    })
-                    .unwrap_or_default(),
+                url: list_url.into(),
+                total_duration: 0,
             },
             playlist
                 .entries
                 .unwrap_or_default()
                 .into_iter()
-                .map(|video| youtube_video_url_string(&video.id))
+                .filter(|video| {
+                    !matches!(
+                        video.title.as_deref(),
+                        Some("[Private video]" | "[Deleted video]")
+                    )
+                })
+                .map(|video| {
+                    let url = youtube_video_url_string(&video.id);
+                    let complete = video.title.is_some()
+                        && video.duration.is_some()
+                        && (video.channel.is_some() || video.uploader.is_some());
+                    MediaListEntry {
+                        metadata: complete.then(|| new_media_from_video(video, url.clone())),
+                        url,
+                    }
+                })
                 .collect(),
         )),
         Ok(_) => Err(MediaResolveError::InvalidMedia),
@@ -196,9 +316,120 @@ pub async fn resolve_media_list(
 
 pub fn get_media_thumbnail_url(media_url: &str) -> Option<String> {
     let url = Url::parse(media_url).ok()?;
-    if let YoutubeUrlParseResult::Video(id) = check_normalized_youtube_url(&url) {
+    if let YoutubeUrlParseResult::Video(id)
+    | YoutubeUrlParseResult::VideoInPlaylist { video_id: id, .. } =
+        check_normalized_youtube_url(&url)
+    {
         Some(format!("https://img.youtube.com/vi/{id}/maxresdefault.jpg"))
     } else {
         None
     }
 }
+
+pub struct YoutubeResolver;
+
+#[async_trait]
+impl MediaResolver for YoutubeResolver {
+    fn name(&self) -> &'static str {
+        "youtube"
+    }
+
+    fn media_type(&self) -> &'static str {
+        "yt"
+    }
+
+    async fn normalize_media_url(&self, url: Url) -> Url {
+        normalize_media_url(url)
+    }
+
+    // a video in a playlist is stored under the video url, and the playlist
+    // under the playlist url
+    fn media_cache_url(&self, url: &Url) -> Option<Url> {
+        match check_normalized_youtube_url(url) {
+            YoutubeUrlParseResult::VideoInPlaylist { video_id, .. } => {
+                Some(youtube_video_url(&video_id))
+            }
+            _ => None,
+        }
+    }
+
+    fn media_list_cache_url(&self, url: &Url) -> Option<Url> {
+        match check_normalized_youtube_url(url) {
+            YoutubeUrlParseResult::VideoInPlaylist { list_id, .. } => {
+                Some(youtube_list_url(&list_id))
+            }
+            _ => None,
+        }
+    }
+
+    async fn resolve_media(&self, url: &Url) -> Result<NewMedia<'static>, MediaResolveError> {
+        resolve_media(url).await
+    }
+
+    async fn resolve_media_list(
+        &self,
+        url: &Url,
+    ) -> Result<(NewMediaList<'static>, Vec<MediaListEntry>), MediaResolveError> {
+        resolve_media_list(url).await
+    }
+
+    fn get_media_thumbnail_url(&self, media_url: &str) -> Option<String> {
+        get_media_thumbnail_url(media_url)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::resolvers::ResolverRegistry;
+
+    #[tokio::test]
+    async fn youtube_urls_are_normalized() {
+        const VIDEO: &str = "https://youtu.be/dQw4w9WgXcQ";
+        const LIST: &str = "https://youtube.com/playlist?list=PLrAXtmErZgOeiKm4sgNOknGvNjby9efdf";
+        const VIDEO_IN_LIST: &str =
+            "https://youtu.be/dQw4w9WgXcQ?list=PLrAXtmErZgOeiKm4sgNOknGvNjby9efdf";
+        let cases = [
+            ("https://youtu.be/dQw4w9WgXcQ", VIDEO),
+            ("https://www.youtube.com/watch?v=dQw4w9WgXcQ&t=42", VIDEO),
+            ("https://www.youtube.com/shorts/dQw4w9WgXcQ", VIDEO),
+            ("https://www.youtube.com/embed/dQw4w9WgXcQ?autoplay=1", VIDEO),
+            ("https://www.youtube.com/live/dQw4w9WgXcQ?si=abc", VIDEO),
+            ("https://music.youtube.com/watch?v=dQw4w9WgXcQ", VIDEO),
+            ("https://m.youtube.com/watch?v=dQw4w9WgXcQ", VIDEO),
+            ("https://www.youtube-nocookie.com/embed/dQw4w9WgXcQ", VIDEO),
+            ("http://www.youtube.com/watch?v=dQw4w9WgXcQ", VIDEO),
+            ("youtube.com/watch?v=dQw4w9WgXcQ", VIDEO),
+            ("youtu.be/dQw4w9WgXcQ", VIDEO),
+            (
+                "https://www.youtube.com/watch?v=dQw4w9WgXcQ&list=PLrAXtmErZgOeiKm4sgNOknGvNjby9efdf&index=3",
+                VIDEO_IN_LIST,
+            ),
+            (
+                "https://www.youtube.com/playlist?list=PLrAXtmErZgOeiKm4sgNOknGvNjby9efdf",
+                LIST,
+            ),
+            (
+                "https://www.youtube.com/embed/videoseries?list=PLrAXtmErZgOeiKm4sgNOknGvNjby9efdf",
+                LIST,
+            ),
+            (
+                "https://music.youtube.com/playlist?list=PLrAXtmErZgOeiKm4sgNOknGvNjby9efdf",
+                LIST,
+            ),
+            ("yt.be/search:never gonna", "https://yt.be/search:never%20gonna"),
+            ("https://yt.be/search:a?b#c", "https://yt.be/search:a%3Fb%23c"),
+            ("youtu.be/search5:never gonna", "https://yt.be/search5:never%20gonna"),
+            ("yt.be/search100:x", "https://yt.be/search50:x"),
+            // not youtube urls, left alone
+            ("https://youtu.be/too-short", "https://youtu.be/too-short"),
+            ("https://youtube.com/channel/UC", "https://youtube.com/channel/UC"),
+            ("https://example.com/watch?v=dQw4w9WgXcQ", "https://example.com/watch?v=dQw4w9WgXcQ"),
+        ];
+
+        let resolvers = ResolverRegistry::default();
+        for (input, expected) in cases {
+            let url = resolvers.normalize_media_url(input).await.unwrap();
+            assert_eq!(url.as_str(), expected, "normalizing {input}");
+        }
+    }
+}
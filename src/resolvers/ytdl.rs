@@ -0,0 +1,276 @@
+use std::{
+    borrow::Cow,
+    collections::HashMap,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use anyhow::{anyhow, Result};
+use async_trait::async_trait;
+use lazy_static::lazy_static;
+use tokio::sync::Mutex;
+use url::Url;
+use youtube_dl::{SingleVideo, YoutubeDl, YoutubeDlOutput};
+
+use crate::db::media::{MediaId, NewMedia, NewMediaList};
+
+use super::{youtube::is_youtube_url, MediaListEntry, MediaResolveError, MediaResolver};
+
+lazy_static! {
+    static ref FORCE_IPV4: bool = std::env::var("YTDL_FORCE_IPV4")
+        .ok()
+        .and_then(|env| env.parse::<bool>().ok())
+        .unwrap_or_default();
+}
+
+pub(super) fn ytdl_builder(url: impl Into<String>) -> YoutubeDl {
+    let mut builder = YoutubeDl::new(url);
+    builder.extra_arg("--ignore-no-formats-error");
+    if *FORCE_IPV4 {
+        builder.extra_arg("--force-ipv4");
+    }
+    builder
+}
+
+pub(super) async fn run_ytdl(url: impl Into<String>) -> Result<YoutubeDlOutput, youtube_dl::Error> {
+    ytdl_builder(url).run_async().await
+}
+
+/// Tell apart urls no extractor understands from medias that are gone and
+/// from other failures, using the error output of yt-dlp.
+fn map_ytdl_exit_error(stderr: &str) -> MediaResolveError {
+    if stderr.contains("Unsupported URL") {
+        MediaResolveError::UnsupportedUrl
+    } else if ["not available", "HTTP Error 404", "HTTP Error 410"]
+        .iter()
+        .any(|pattern| stderr.contains(pattern))
+    {
+        MediaResolveError::MediaNotFound
+    } else {
+        MediaResolveError::FailedProcessing(anyhow!("{}", stderr.trim()))
+    }
+}
+
+fn map_ytdl_error(error: youtube_dl::Error) -> MediaResolveError {
+    match error {
+        youtube_dl::Error::Json(_) => MediaResolveError::MediaNotFound,
+        youtube_dl::Error::ExitCode { code, stderr } => {
+            tracing::warn!("yt-dlp exited with code {code}: {stderr}");
+            map_ytdl_exit_error(&stderr)
+        }
+        e => MediaResolveError::FailedProcessing(e.into()),
+    }
+}
+
+fn check_ytdl_url(url: &Url) -> Result<(), MediaResolveError> {
+    if (url.scheme() == "http" || url.scheme() == "https") && !is_youtube_url(url) {
+        Ok(())
+    } else {
+        Err(MediaResolveError::UnsupportedUrl)
+    }
+}
+
+/// yt-dlp dates are formatted as `YYYYMMDD`.
+fn format_release_date(date: String) -> String {
+    if date.len() == 8 && date.chars().all(|c| c.is_ascii_digit()) {
+        format!("{}-{}-{}", &date[..4], &date[4..6], &date[6..])
+    } else {
+        date
+    }
+}
+
+fn new_media_from_video(video: SingleVideo, fallback_url: &Url) -> NewMedia<'static> {
+    NewMedia {
+        title: video
+            .track
+            .or(video.title)
+            .map(Cow::Owned)
+            .unwrap_or("<empty title>".into()),
+        artist: video
+            .artist
+            .or(video.creator)
+            .or(video.uploader)
+            .or(video.channel)
+            .map(Cow::Owned)
+            .unwrap_or("<empty artist>".into()),
+        duration: video
+            .duration
+            .and_then(|v| v.as_f64())
+            .map(|v| v.round() as i32),
+        // direct media urls usually expire, so only the webpage url is stored
+        url: video
+            .webpage_url
+            .unwrap_or_else(|| fallback_url.to_string())
+            .into(),
+        media_type: "ytdl".into(),
+        album: video.album.map(Cow::Owned),
+        track: video.track_number.and_then(|track| track.parse().ok()),
+        release_date: video.release_date.map(format_release_date).map(Cow::Owned),
+        extractor: video.extractor_key.or(video.extractor).map(Cow::Owned),
+    }
+}
+
+pub async fn resolve_media(url: &Url) -> Result<NewMedia<'static>, MediaResolveError> {
+    check_ytdl_url(url)?;
+    match run_ytdl(url.as_str()).await.map_err(map_ytdl_error)? {
+        YoutubeDlOutput::SingleVideo(video) => Ok(new_media_from_video(*video, url)),
+        YoutubeDlOutput::Playlist(_) => Err(MediaResolveError::InvalidMedia),
+    }
+}
+
+pub async fn resolve_media_list(
+    url: &Url,
+) -> Result<(NewMediaList<'static>, Vec<MediaListEntry>), MediaResolveError> {
+    check_ytdl_url(url)?;
+    match run_ytdl(url.as_str()).await.map_err(map_ytdl_error)? {
+        YoutubeDlOutput::Playlist(playlist) => {
+            let entries = playlist.entries.unwrap_or_default();
+            Ok((
+                NewMediaList {
+                    title: playlist
+                        .title
+                        .map(Cow::Owned)
+                        .unwrap_or("<empty title>".into()),
+                    artist: playlist
+                        .uploader
+                        .map(Cow::Owned)
+                        .unwrap_or("<empty artist>".into()),
+                    url: url.to_string().into(),
+                    total_duration: 0,
+                },
+                entries
+                    .into_iter()
+                    .filter_map(|video| video.webpage_url.or(video.url))
+                    .map(MediaListEntry::from)
+                    .collect(),
+            ))
+        }
+        YoutubeDlOutput::SingleVideo(_) => Err(MediaResolveError::InvalidMedia),
+    }
+}
+
+/// Resolve the webpage url of a `ytdl` media into a direct media url that
+/// can be played by a `<video>` element.
+///
+/// These urls usually expire after a few hours, so they are not stored with
+/// the media but cached in a [`DirectUrlCache`].
+pub async fn resolve_direct_url(webpage_url: &str) -> Result<String, MediaResolveError> {
+    let mut builder = ytdl_builder(webpage_url);
+    // merged formats have no direct url, so pick a single file, falling back
+    // to audio-only formats for sites like SoundCloud
+    builder.format("b/ba");
+    match builder.run_async().await.map_err(map_ytdl_error)? {
+        YoutubeDlOutput::SingleVideo(video) => video
+            .url
+            .ok_or_else(|| anyhow!("yt-dlp returned no direct url for {webpage_url}").into()),
+        YoutubeDlOutput::Playlist(_) => Err(MediaResolveError::InvalidMedia),
+    }
+}
+
+/// Direct urls are usually valid for a few hours, so they are resolved
+/// again well before that.
+const DIRECT_URL_TTL: Duration = Duration::from_secs(30 * 60);
+
+type DirectUrlEntry = Arc<Mutex<Option<(String, Instant)>>>;
+
+/// Direct urls of `ytdl` medias, so that yt-dlp does not run again for every
+/// seek or range request of the browser.
+#[derive(Default)]
+pub struct DirectUrlCache {
+    entries: std::sync::Mutex<HashMap<MediaId, DirectUrlEntry>>,
+}
+
+impl DirectUrlCache {
+    /// The direct url of the media `media_id` with webpage url `webpage_url`,
+    /// resolving it if it is not cached yet or has expired. Concurrent calls
+    /// for the same media share a single yt-dlp run.
+    pub async fn get_or_resolve(
+        &self,
+        media_id: MediaId,
+        webpage_url: &str,
+    ) -> Result<String, MediaResolveError> {
+        let entry = {
+            let mut entries = self.entries.lock().expect("direct url cache poisoned");
+            // forget expired urls that nobody is resolving
+            entries.retain(|_, entry| {
+                Arc::strong_count(entry) > 1
+                    || entry.try_lock().map_or(true, |cached| {
+                        cached
+                            .as_ref()
+                            .is_some_and(|(_, resolved_at)| resolved_at.elapsed() < DIRECT_URL_TTL)
+                    })
+            });
+            entries.entry(media_id).or_default().clone()
+        };
+
+        let mut cached = entry.lock().await;
+        if let Some((url, resolved_at)) = cached.as_ref() {
+            if resolved_at.elapsed() < DIRECT_URL_TTL {
+                return Ok(url.clone());
+            }
+        }
+        let url = resolve_direct_url(webpage_url).await?;
+        *cached = Some((url.clone(), Instant::now()));
+        Ok(url)
+    }
+}
+
+/// Fallback resolver for every other site supported by yt-dlp (SoundCloud,
+/// Bandcamp, Niconico, Vimeo, ...).
+pub struct YtdlResolver;
+
+#[async_trait]
+impl MediaResolver for YtdlResolver {
+    fn name(&self) -> &'static str {
+        "yt-dlp"
+    }
+
+    fn media_type(&self) -> &'static str {
+        "ytdl"
+    }
+
+    async fn resolve_media(&self, url: &Url) -> Result<NewMedia<'static>, MediaResolveError> {
+        resolve_media(url).await
+    }
+
+    async fn resolve_media_list(
+        &self,
+        url: &Url,
+    ) -> Result<(NewMediaList<'static>, Vec<MediaListEntry>), MediaResolveError> {
+        resolve_media_list(url).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ytdl_errors_are_told_apart() {
+        let cases = [
+            (
+                "ERROR: Unsupported URL: https://example.com/",
+                "Unsupported url",
+            ),
+            (
+                "ERROR: [soundcloud] user/track: Unable to download JSON metadata: HTTP Error 404: Not Found",
+                "Resource referenced by url not found",
+            ),
+            (
+                "ERROR: [vimeo] 1234: This video is not available",
+                "Resource referenced by url not found",
+            ),
+            (
+                "ERROR: [generic] Unable to download webpage: HTTP Error 429: Too Many Requests\n",
+                "Generic error: ERROR: [generic] Unable to download webpage: HTTP Error 429: Too Many Requests",
+            ),
+        ];
+        for (stderr, expected) in cases {
+            assert_eq!(
+                map_ytdl_exit_error(stderr).to_string(),
+                expected,
+                "{stderr}"
+            );
+        }
+    }
+}
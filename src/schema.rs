@@ -1,11 +1,32 @@
 // @generated automatically by Diesel CLI.
 
+diesel::table! {
+    jobs (id) {
+        id -> Integer,
+        playlist_id -> Integer,
+        url -> Text,
+        position -> Text,
+        prefer_list -> Bool,
+        status -> Text,
+        error -> Nullable<Text>,
+        add_timestamp -> Timestamp,
+        update_timestamp -> Timestamp,
+    }
+}
+
+diesel::table! {
+    media_list_entries (media_list_id, position) {
+        media_list_id -> Integer,
+        position -> Integer,
+        media_id -> Integer,
+    }
+}
+
 diesel::table! {
     media_lists (id) {
         id -> Integer,
         title -> Nullable<Text>,
         artist -> Nullable<Text>,
-        media_ids -> Text,
         url -> Text,
         add_timestamp -> Timestamp,
         total_duration -> Integer,
@@ -24,6 +45,22 @@ diesel::table! {
         views -> Integer,
         alt_title -> Nullable<Text>,
         alt_artist -> Nullable<Text>,
+        album -> Nullable<Text>,
+        track -> Nullable<Integer>,
+        release_date -> Nullable<Text>,
+        extractor -> Nullable<Text>,
+    }
+}
+
+diesel::table! {
+    play_history (id) {
+        id -> Integer,
+        media_id -> Integer,
+        playlist_id -> Nullable<Integer>,
+        playlist_item_id -> Nullable<Integer>,
+        started_at -> Timestamp,
+        ended_at -> Nullable<Timestamp>,
+        completed -> Bool,
     }
 }
 
@@ -35,6 +72,7 @@ diesel::table! {
         prev -> Nullable<Integer>,
         next -> Nullable<Integer>,
         add_timestamp -> Timestamp,
+        media_list_id -> Nullable<Integer>,
     }
 }
 
@@ -48,12 +86,39 @@ diesel::table! {
         current_item -> Nullable<Integer>,
         num_items -> Integer,
         total_duration -> Integer,
+        repeat_mode -> Text,
+        stop_after_current -> Bool,
+        shuffle -> Bool,
     }
 }
 
+diesel::table! {
+    shuffle_order (playlist_id, playlist_item_id) {
+        playlist_id -> Integer,
+        position -> Integer,
+        playlist_item_id -> Integer,
+        played -> Bool,
+    }
+}
+
+diesel::joinable!(jobs -> playlists (playlist_id));
+diesel::joinable!(media_list_entries -> media_lists (media_list_id));
+diesel::joinable!(media_list_entries -> medias (media_id));
+diesel::joinable!(play_history -> medias (media_id));
+diesel::joinable!(play_history -> playlists (playlist_id));
+diesel::joinable!(playlist_items -> media_lists (media_list_id));
+diesel::joinable!(playlist_items -> medias (media_id));
+diesel::joinable!(playlist_items -> playlists (playlist_id));
+diesel::joinable!(shuffle_order -> playlist_items (playlist_item_id));
+diesel::joinable!(shuffle_order -> playlists (playlist_id));
+
 diesel::allow_tables_to_appear_in_same_query!(
+    jobs,
+    media_list_entries,
     media_lists,
     medias,
+    play_history,
     playlist_items,
     playlists,
+    shuffle_order,
 );